@@ -0,0 +1,48 @@
+#![feature(async_await, futures_api, pin, arbitrary_self_types, await_macro)]
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{local_waker_from_nonlocal, Poll, Wake};
+
+struct NoopWake;
+
+impl Wake for NoopWake {
+    fn wake(_: &Arc<Self>) {}
+}
+
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, lw: &std::task::LocalWaker) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            lw.wake();
+            Poll::Pending
+        }
+    }
+}
+
+async fn add(a: u8, b: u8) -> u8 {
+    await!(YieldOnce(false));
+    a + b
+}
+
+fn main() {
+    let waker = local_waker_from_nonlocal(Arc::new(NoopWake));
+    let mut fut = Box::pinned(add(40, 2));
+    let mut polls = 0;
+    let res = loop {
+        polls += 1;
+        if let Poll::Ready(res) = fut.as_mut().poll(&waker) {
+            break res;
+        }
+    };
+    assert_eq!(res, 42);
+    assert_eq!(polls, 2);
+    println!("async fn returned {}", res);
+}
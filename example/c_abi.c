@@ -0,0 +1,107 @@
+#include <stdint.h>
+
+struct Pair {
+    uint8_t a;
+    uint32_t b;
+};
+
+struct Three {
+    uint8_t a;
+    uint8_t b;
+    uint8_t c;
+};
+
+struct Mixed {
+    double a;
+    uint8_t b;
+};
+
+struct Floats {
+    float a;
+    float b;
+    float c;
+};
+
+struct Big {
+    uint64_t a;
+    uint64_t b;
+    uint8_t c;
+};
+
+struct Pair swap_pair(struct Pair p) {
+    struct Pair res = { (uint8_t)p.b, p.a };
+    return res;
+}
+
+struct Three rotate_three(struct Three t) {
+    struct Three res = { t.b, t.c, t.a };
+    return res;
+}
+
+struct Mixed double_mixed(struct Mixed m) {
+    struct Mixed res = { m.a * 2.0, m.b * 2 };
+    return res;
+}
+
+float sum_floats(struct Floats f) {
+    return f.a + f.b + f.c;
+}
+
+struct Big make_big(uint8_t c) {
+    struct Big res = { 1, 2, c };
+    return res;
+}
+
+uint8_t call_with_mixed(struct Pair (*f)(struct Mixed, struct Three)) {
+    struct Mixed m = { 1.5, 7 };
+    struct Three t = { 1, 2, 3 };
+    struct Pair p = f(m, t);
+    return p.a + (uint8_t)p.b;
+}
+
+uint8_t sum_big(uint8_t x, struct Big b, double y) {
+    return x + (uint8_t)b.a + (uint8_t)b.b + b.c + (uint8_t)y;
+}
+
+uint8_t call_with_big(uint8_t (*f)(struct Big, uint8_t)) {
+    struct Big b = { 1, 2, 3 };
+    return f(b, 4);
+}
+
+struct TwoInts {
+    uint64_t a;
+    uint64_t b;
+};
+
+struct TwoDoubles {
+    double a;
+    double b;
+};
+
+uint64_t split_ints(uint64_t a1, uint64_t a2, uint64_t a3, uint64_t a4, uint64_t a5,
+                    struct TwoInts t, uint64_t a6) {
+    return a1 + a2 + a3 + a4 + a5 + t.a * 10 + t.b * 100 + a6 * 1000;
+}
+
+uint64_t call_with_split_ints(uint64_t (*f)(uint64_t, uint64_t, uint64_t, uint64_t, uint64_t,
+                                            struct TwoInts, uint64_t)) {
+    struct TwoInts t = { 2, 3 };
+    return f(1, 1, 1, 1, 1, t, 4);
+}
+
+uint64_t split_doubles(double d1, double d2, double d3, double d4, double d5, double d6, double d7,
+                       struct TwoDoubles t, double d8) {
+    return (uint64_t)(d1 + d2 + d3 + d4 + d5 + d6 + d7 + t.a * 10 + t.b * 100 + d8 * 1000);
+}
+
+uint64_t many_args(uint64_t a1, uint64_t a2, uint64_t a3, uint64_t a4, uint64_t a5, uint64_t a6,
+                   uint64_t a7, struct Big b, uint64_t a8, double d) {
+    return a1 + a2 + a3 + a4 + a5 + a6 + a7 * 10 + b.a * 100 + b.b * 1000 + b.c * 10000
+        + a8 * 100000 + (uint64_t)d * 1000000;
+}
+
+uint64_t call_with_many_args(uint64_t (*f)(uint64_t, uint64_t, uint64_t, uint64_t, uint64_t,
+                                           uint64_t, uint64_t, struct Big, uint64_t, double)) {
+    struct Big b = { 3, 4, 5 };
+    return f(1, 1, 1, 1, 1, 1, 2, b, 6, 7.0);
+}
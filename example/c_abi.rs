@@ -0,0 +1,167 @@
+// Checks passing and returning structs by value to and from C code compiled by the system cc
+
+#![feature(no_core, start)]
+#![no_core]
+#![allow(dead_code)]
+
+extern crate mini_core;
+
+use mini_core::*;
+
+#[repr(C)]
+struct Pair {
+    a: u8,
+    b: u32,
+}
+
+#[repr(C)]
+struct Three {
+    a: u8,
+    b: u8,
+    c: u8,
+}
+
+#[repr(C)]
+struct Mixed {
+    a: f64,
+    b: u8,
+}
+
+#[repr(C)]
+struct Floats {
+    a: f32,
+    b: f32,
+    c: f32,
+}
+
+#[repr(C)]
+struct Big {
+    a: u64,
+    b: u64,
+    c: u8,
+}
+
+#[repr(C)]
+struct TwoInts {
+    a: u64,
+    b: u64,
+}
+
+#[repr(C)]
+struct TwoDoubles {
+    a: f64,
+    b: f64,
+}
+
+#[link(name = "c")]
+extern "C" {}
+
+#[link(name = "c_abi", kind = "static")]
+extern "C" {
+    fn swap_pair(p: Pair) -> Pair;
+    fn rotate_three(t: Three) -> Three;
+    fn double_mixed(m: Mixed) -> Mixed;
+    fn sum_floats(f: Floats) -> f32;
+    fn make_big(c: u8) -> Big;
+    fn call_with_mixed(f: extern "C" fn(Mixed, Three) -> Pair) -> u8;
+    fn sum_big(x: u8, b: Big, y: f64) -> u8;
+    fn call_with_big(f: extern "C" fn(Big, u8) -> u8) -> u8;
+    fn split_ints(a1: u64, a2: u64, a3: u64, a4: u64, a5: u64, t: TwoInts, a6: u64) -> u64;
+    fn call_with_split_ints(
+        f: extern "C" fn(u64, u64, u64, u64, u64, TwoInts, u64) -> u64,
+    ) -> u64;
+    fn split_doubles(
+        d1: f64, d2: f64, d3: f64, d4: f64, d5: f64, d6: f64, d7: f64,
+        t: TwoDoubles, d8: f64,
+    ) -> u64;
+    fn many_args(
+        a1: u64, a2: u64, a3: u64, a4: u64, a5: u64, a6: u64, a7: u64,
+        b: Big, a8: u64, d: f64,
+    ) -> u64;
+    fn call_with_many_args(
+        f: extern "C" fn(u64, u64, u64, u64, u64, u64, u64, Big, u64, f64) -> u64,
+    ) -> u64;
+}
+
+extern "C" fn rust_callback(m: Mixed, t: Three) -> Pair {
+    Pair {
+        a: t.c,
+        b: (m.a as u8 + m.b) as u32,
+    }
+}
+
+extern "C" fn rust_big_callback(b: Big, x: u8) -> u8 {
+    b.a as u8 + b.b as u8 + b.c + x
+}
+
+extern "C" fn rust_split_ints_callback(
+    a1: u64, a2: u64, a3: u64, a4: u64, a5: u64, t: TwoInts, a6: u64,
+) -> u64 {
+    a1 + a2 + a3 + a4 + a5 + t.a * 10 + t.b * 100 + a6 * 1000
+}
+
+extern "C" fn rust_many_args_callback(
+    a1: u64, a2: u64, a3: u64, a4: u64, a5: u64, a6: u64, a7: u64,
+    b: Big, a8: u64, d: f64,
+) -> u64 {
+    a1 + a2 + a3 + a4 + a5 + a6 + a7 * 10 + b.a * 100 + b.b * 1000 + b.c as u64 * 10000
+        + a8 * 100000 + d as u64 * 1000000
+}
+
+macro_rules! assert_eq {
+    ($l:expr, $r: expr) => {
+        if $l != $r {
+            panic(&(stringify!($l != $r), file!(), line!(), 0));
+        }
+    }
+}
+
+#[start]
+fn main(_argc: isize, _argv: *const *const u8) -> isize {
+    unsafe {
+        let pair = swap_pair(Pair { a: 1, b: 2 });
+        assert_eq!(pair.a, 2);
+        assert_eq!(pair.b as u8, 1);
+
+        let three = rotate_three(Three { a: 1, b: 2, c: 3 });
+        assert_eq!(three.a, 2);
+        assert_eq!(three.b, 3);
+        assert_eq!(three.c, 1);
+
+        let mixed = double_mixed(Mixed { a: 1.5, b: 4 });
+        assert_eq!(mixed.a as u8, 3);
+        assert_eq!(mixed.b, 8);
+
+        let sum = sum_floats(Floats { a: 1.0, b: 2.0, c: 3.0 });
+        assert_eq!(sum as u8, 6);
+
+        let big = make_big(42);
+        assert_eq!(big.a as u8, 1);
+        assert_eq!(big.b as u8, 2);
+        assert_eq!(big.c, 42);
+
+        // 3 + (1 + 7)
+        assert_eq!(call_with_mixed(rust_callback), 11);
+
+        // Structs bigger than two eightbytes are passed in memory
+        assert_eq!(sum_big(1, Big { a: 2, b: 3, c: 4 }, 5.0), 15);
+        assert_eq!(call_with_big(rust_big_callback), 10);
+
+        // Structs of two eightbytes are passed in memory when only one register is left for them
+        assert_eq!(split_ints(1, 1, 1, 1, 1, TwoInts { a: 2, b: 3 }, 4) as usize, 4325);
+        assert_eq!(call_with_split_ints(rust_split_ints_callback) as usize, 4325);
+        assert_eq!(
+            split_doubles(1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, TwoDoubles { a: 2.0, b: 3.0 }, 4.0) as usize,
+            4327
+        );
+
+        // Arguments which don't fit in registers are interleaved with structs passed in memory
+        assert_eq!(
+            many_args(1, 1, 1, 1, 1, 1, 2, Big { a: 3, b: 4, c: 5 }, 6, 7.0) as usize,
+            7654326
+        );
+        assert_eq!(call_with_many_args(rust_many_args_callback) as usize, 7654326);
+    }
+
+    0
+}
@@ -169,9 +169,13 @@ fn float_cast(a: f32, b: f64) -> (f64, f32) {
     (a as f64, b as f32)
 }
 
-/*fn int_to_float(a: u8, b: i32) -> (f64, f32) {
+fn int_to_float(a: u8, b: i32) -> (f64, f32) {
     (a as f64, b as f32)
-}*/
+}
+
+fn float_to_int(a: f32, b: f64) -> (u8, i16, u32, i64) {
+    (a as u8, a as i16, b as u32, b as i64)
+}
 
 fn make_array() -> [u8; 3] {
     [42, 0, 5]
@@ -206,3 +210,23 @@ fn get_sized_field_ref_from_unsized_type(u: &Unsized) -> &u8 {
 fn get_unsized_field_ref_from_unsized_type(u: &Unsized) -> &str {
     &u.1
 }
+
+fn u128_arith(a: u128, b: u128) -> (u128, u128, u128) {
+    (a + b, a * b, a / b)
+}
+
+fn i128_shift(a: i128, b: u32) -> (i128, i128) {
+    (a << b, a >> b)
+}
+
+fn u128_match(a: u128) -> u8 {
+    match a {
+        0 => 1,
+        0xffff_ffff_ffff_ffff_ffff => 2,
+        _ => 3,
+    }
+}
+
+fn u128_as_u8(a: u128) -> u8 {
+    a as u8
+}
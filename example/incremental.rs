@@ -0,0 +1,28 @@
+// Crate with a codegen unit per module, used by test.sh to check that only the codegen units of
+// changed functions are recompiled
+
+#![feature(no_core, start)]
+#![no_core]
+
+extern crate mini_core;
+
+#[link(name = "c")]
+extern "C" {}
+
+mod a {
+    pub fn value() -> u8 {
+        1
+    }
+}
+
+mod b {
+    // test.sh changes the returned value before the last build
+    pub fn value() -> u8 {
+        2
+    }
+}
+
+#[start]
+fn main(_argc: isize, _argv: *const *const u8) -> isize {
+    (a::value() + b::value()) as isize
+}
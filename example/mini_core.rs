@@ -35,11 +35,16 @@ unsafe impl Copy for u8 {}
 unsafe impl Copy for u16 {}
 unsafe impl Copy for u32 {}
 unsafe impl Copy for u64 {}
+unsafe impl Copy for u128 {}
 unsafe impl Copy for usize {}
 unsafe impl Copy for i8 {}
 unsafe impl Copy for i16 {}
 unsafe impl Copy for i32 {}
+unsafe impl Copy for i64 {}
+unsafe impl Copy for i128 {}
 unsafe impl Copy for isize {}
+unsafe impl Copy for f32 {}
+unsafe impl Copy for f64 {}
 unsafe impl Copy for char {}
 unsafe impl<'a, T: ?Sized> Copy for &'a T {}
 unsafe impl<T: ?Sized> Copy for *const T {}
@@ -95,6 +100,22 @@ impl Mul for u8 {
     }
 }
 
+impl Mul for u64 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        self * rhs
+    }
+}
+
+impl Mul for u128 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        self * rhs
+    }
+}
+
 #[lang = "add"]
 pub trait Add<RHS = Self> {
     type Output;
@@ -110,6 +131,22 @@ impl Add for u8 {
     }
 }
 
+impl Add for u64 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        self + rhs
+    }
+}
+
+impl Add for u128 {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        self + rhs
+    }
+}
+
 #[lang = "sub"]
 pub trait Sub<RHS = Self> {
     type Output;
@@ -125,6 +162,98 @@ impl Sub for usize {
     }
 }
 
+#[lang = "div"]
+pub trait Div<RHS = Self> {
+    type Output;
+
+    fn div(self, rhs: RHS) -> Self::Output;
+}
+
+impl Div for u128 {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        self / rhs
+    }
+}
+
+#[lang = "shl"]
+pub trait Shl<RHS = Self> {
+    type Output;
+
+    fn shl(self, rhs: RHS) -> Self::Output;
+}
+
+impl Shl<u32> for u8 {
+    type Output = Self;
+
+    fn shl(self, rhs: u32) -> Self {
+        self << rhs
+    }
+}
+
+impl Shl<u32> for i64 {
+    type Output = Self;
+
+    fn shl(self, rhs: u32) -> Self {
+        self << rhs
+    }
+}
+
+impl Shl<u32> for i128 {
+    type Output = Self;
+
+    fn shl(self, rhs: u32) -> Self {
+        self << rhs
+    }
+}
+
+#[lang = "shr"]
+pub trait Shr<RHS = Self> {
+    type Output;
+
+    fn shr(self, rhs: RHS) -> Self::Output;
+}
+
+impl Shr<u128> for u8 {
+    type Output = Self;
+
+    fn shr(self, rhs: u128) -> Self {
+        self >> rhs
+    }
+}
+
+impl Shr<u8> for u64 {
+    type Output = Self;
+
+    fn shr(self, rhs: u8) -> Self {
+        self >> rhs
+    }
+}
+
+impl Shr<u32> for i128 {
+    type Output = Self;
+
+    fn shr(self, rhs: u32) -> Self {
+        self >> rhs
+    }
+}
+
+#[lang = "rem"]
+pub trait Rem<RHS = Self> {
+    type Output;
+
+    fn rem(self, rhs: RHS) -> Self::Output;
+}
+
+impl Rem for usize {
+    type Output = Self;
+
+    fn rem(self, rhs: Self) -> Self {
+        self % rhs
+    }
+}
+
 #[lang = "bitor"]
 pub trait BitOr<RHS = Self> {
     type Output;
@@ -164,6 +293,15 @@ impl PartialEq for u8 {
     }
 }
 
+impl PartialEq for usize {
+    fn eq(&self, other: &usize) -> bool {
+        (*self) == (*other)
+    }
+    fn ne(&self, other: &usize) -> bool {
+        (*self) != (*other)
+    }
+}
+
 impl PartialEq for char {
     fn eq(&self, other: &char) -> bool {
         (*self) == (*other)
@@ -189,6 +327,22 @@ pub trait Neg {
     fn neg(self) -> Self::Output;
 }
 
+impl Neg for i8 {
+    type Output = i8;
+
+    fn neg(self) -> i8 {
+        -self
+    }
+}
+
+impl Neg for i64 {
+    type Output = i64;
+
+    fn neg(self) -> i64 {
+        -self
+    }
+}
+
 impl Neg for isize {
     type Output = isize;
 
@@ -197,6 +351,14 @@ impl Neg for isize {
     }
 }
 
+impl Neg for f64 {
+    type Output = f64;
+
+    fn neg(self) -> f64 {
+        -self
+    }
+}
+
 pub enum Option<T> {
     Some(T),
     None,
@@ -221,6 +383,20 @@ pub trait FnMut<Args>: FnOnce<Args> {
     extern "rust-call" fn call_mut(&mut self, args: Args) -> Self::Output;
 }
 
+#[lang = "generator_state"]
+pub enum GeneratorState<Y, R> {
+    Yielded(Y),
+    Complete(R),
+}
+
+#[lang = "generator"]
+pub trait Generator {
+    type Yield;
+    type Return;
+
+    unsafe fn resume(&mut self) -> GeneratorState<Self::Yield, Self::Return>;
+}
+
 #[lang = "panic"]
 pub fn panic(_expr_file_line_col: &(&'static str, &'static str, u32, u32)) -> ! {
     unsafe {
@@ -270,6 +446,9 @@ pub mod intrinsics {
         pub fn uninit<T>() -> T;
         pub fn ctlz_nonzero<T>(x: T) -> T;
         pub fn needs_drop<T>() -> bool;
+        pub fn add_with_overflow<T>(x: T, y: T) -> (T, bool);
+        pub fn sub_with_overflow<T>(x: T, y: T) -> (T, bool);
+        pub fn mul_with_overflow<T>(x: T, y: T) -> (T, bool);
     }
 }
 
@@ -1,6 +1,6 @@
 // Adapted from https://github.com/sunfishcode/mir2cranelift/blob/master/rust-examples/nocore-hello-world.rs
 
-#![feature(no_core, unboxed_closures, start, lang_items, box_syntax)]
+#![feature(no_core, unboxed_closures, start, lang_items, box_syntax, generators, global_asm, asm, thread_local, linkage, slice_patterns)]
 #![no_core]
 #![allow(dead_code)]
 
@@ -11,6 +11,43 @@ use mini_core::*;
 #[link(name = "c")]
 extern "C" {
     fn puts(s: *const u8);
+    fn printf(format: *const i8, ...) -> i32;
+}
+
+// Provides the lib calls for 128bit ints, like `__fixdfti`
+#[link(name = "gcc")]
+extern "C" {}
+
+// TODO remove cfg when jit supports global asm
+#[cfg(not(jit))]
+global_asm!("
+    .global add_asm
+    add_asm:
+        movq %rdi, %rax
+        addq %rsi, %rax
+        ret
+");
+
+#[cfg(not(jit))]
+extern "C" {
+    fn add_asm(a: u64, b: u64) -> u64;
+}
+
+// The symbol name of the method contains characters like `$`
+#[cfg(not(jit))]
+trait AsmAdd {
+    fn asm_add(self, other: u64) -> u64;
+}
+
+#[cfg(not(jit))]
+impl AsmAdd for u64 {
+    fn asm_add(self, other: u64) -> u64 {
+        let sum: u64;
+        unsafe {
+            asm!("addq $2, $0" : "=r"(sum) : "0"(self), "r"(other) : "cc", "memory");
+        }
+        sum
+    }
 }
 
 unsafe extern "C" fn my_puts(s: *const u8) {
@@ -66,6 +103,10 @@ impl Drop for NoisyDrop {
     }
 }
 
+impl SomeTrait for NoisyDrop {
+    fn object_safe(&self) {}
+}
+
 impl Drop for NoisyDropInner {
     fn drop(&mut self) {
         unsafe {
@@ -74,6 +115,41 @@ impl Drop for NoisyDropInner {
     }
 }
 
+static mut DROP_COUNT: u8 = 0;
+
+struct CountDrop;
+
+impl Drop for CountDrop {
+    fn drop(&mut self) {
+        unsafe {
+            DROP_COUNT = DROP_COUNT + 1;
+        }
+    }
+}
+
+struct Rgb {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+struct Halves(u16, u16, u16);
+
+struct Meters(u8);
+
+#[repr(C)]
+struct NumAndStr {
+    num: u64,
+    text: &'static str,
+}
+
+const NUM_AND_STR: NumAndStr = NumAndStr {
+    num: 1,
+    text: "relocated\0",
+};
+
+static SECOND_BYTE: &u8 = &[1, 2, 3][1];
+
 enum Ordering {
     Less = -1,
     Equal = 0,
@@ -92,6 +168,64 @@ fn start<T: Termination + 'static>(
 static mut NUM: u8 = 6 * 7;
 static NUM_REF: &'static u8 = unsafe { &NUM };
 
+#[thread_local]
+static mut TLS: u8 = 42;
+
+#[repr(align(4096))]
+struct PageAligned(u8);
+
+unsafe impl Sync for PageAligned {}
+
+// TODO remove cfg when jit supports alignments bigger than 128 bytes
+#[cfg(not(jit))]
+static PAGE_ALIGNED: PageAligned = PageAligned(1);
+
+static mut ZEROED: [u8; 0x100000] = [0; 0x100000];
+
+#[used]
+#[link_section = ".custom_section"]
+static IN_CUSTOM_SECTION: u8 = 7;
+
+#[link_section = ".custom_text"]
+fn in_custom_text_section() -> u8 {
+    42
+}
+
+// TODO remove cfg when jit supports weak imports
+#[cfg(not(jit))]
+extern "C" {
+    #[linkage = "extern_weak"]
+    static __cg_clif_missing_weak_symbol: *const u8;
+    #[linkage = "extern_weak"]
+    static environ: *const u8;
+}
+
+fn array_subslice(arr: [u8; 4]) -> [u8; 2] {
+    let [_, middle.., _] = arr;
+    middle
+}
+
+fn slice_subslice(s: &[u8]) -> &[u8] {
+    match s {
+        [_, rest..] => rest,
+        [] => s,
+    }
+}
+
+fn slice_middle(s: &[u8]) -> &[u8] {
+    match s {
+        [_, middle.., _] => middle,
+        _ => s,
+    }
+}
+
+fn slice_last(s: &[u8]) -> u8 {
+    match s {
+        [.., last] => *last,
+        [] => 0,
+    }
+}
+
 macro_rules! assert {
     ($e:expr) => {
         if !$e {
@@ -121,14 +255,48 @@ fn main() {
         let ptr: *const u8 = hello as *const [u8] as *const u8;
         puts(ptr);
 
+        assert_eq!(TLS, 42);
+        TLS = 43;
+        assert_eq!(TLS, 43);
+
+        ZEROED[42] = 1;
+        assert_eq!(ZEROED[42], 1);
+
+        printf(
+            "Hello %s %d %.1f\n\0" as *const str as *const i8,
+            "printf\0" as *const str as *const i8,
+            42i32,
+            0.5f64,
+        );
+
         // TODO remove when jit supports linking rlibs
         #[cfg(not(jit))]
         {
             let world: Box<&str> = box "World!\0";
             puts(*world as *const str as *const u8);
             world as Box<SomeTrait>;
+
+            let noisy: Box<dyn SomeTrait> = box NoisyDrop {
+                text: "Boxed trait object got dropped!\0",
+                inner: NoisyDropInner,
+            };
+            noisy.object_safe();
+
+            assert_eq!(add_asm(40, 2) as u8, 42);
+
+            let sum: u64;
+            asm!("addq $2, $0" : "=r"(sum) : "0"(40u64), "r"(2u64));
+            assert_eq!(sum as u8, 42);
+            assert_eq!(40u64.asm_add(2) as u8, 42);
+
+            assert_eq!(&PAGE_ALIGNED as *const PageAligned as usize % 4096, 0);
+
+            assert_eq!(__cg_clif_missing_weak_symbol as usize, 0);
+            assert!(environ as usize != 0);
         }
 
+        assert_eq!(in_custom_text_section(), 42);
+
         assert_eq!(intrinsics::size_of_val(hello) as u8, 6);
 
         let chars = &['C', 'h', 'a', 'r', 's'];
@@ -164,10 +332,178 @@ fn main() {
         None => assert!(false),
     }
 
+    // A local of a niche filling enum, which test.sh looks for in the debuginfo
+    let niche_filled_fn_ref: Option<fn()> = FUNC_REF;
+    match niche_filled_fn_ref {
+        Some(_) => {},
+        None => assert!(false),
+    }
+
     match Ordering::Less {
         Ordering::Less => {},
         _ => assert!(false),
     }
 
     [NoisyDropInner, NoisyDropInner];
+
+    // Pointers stored at a non-zero offset of a constant and pointers into the middle of an
+    // allocation are relocations with a non-zero offset or addend
+    let num_and_str = NUM_AND_STR;
+    assert_eq!(num_and_str.num as u8, 1);
+    unsafe {
+        assert_eq!(intrinsics::size_of_val(num_and_str.text), 10);
+        assert_eq!(*(num_and_str.text as *const str as *const u8), b'r');
+    }
+    assert_eq!(*SECOND_BYTE, 2);
+
+    let add_one: fn(u8) -> u8 = |a| a + 1;
+    assert_eq!(add_one(41), 42);
+
+    let shl: fn(u8, u32) -> u8 = |a, b| a << b;
+    assert_eq!(shl(1, 7), 128);
+    let shr: fn(u64, u8) -> u64 = |a, b| a >> b;
+    assert_eq!(shr(0x100_0000_0000, 40) as u8, 1);
+    let shr_u128: fn(u8, u128) -> u8 = |a, b| a >> b;
+    assert_eq!(shr_u128(128, 7), 1);
+
+    // Small aggregates and scalar pairs are passed and returned in registers by the "Rust" abi
+    let rotate_rgb: fn(Rgb) -> Rgb = |c| Rgb { r: c.g, g: c.b, b: c.r };
+    let rgb = rotate_rgb(Rgb { r: 1, g: 2, b: 3 });
+    assert_eq!(rgb.r, 2);
+    assert_eq!(rgb.g, 3);
+    assert_eq!(rgb.b, 1);
+    let reverse_halves: fn(Halves) -> Halves = |h| Halves(h.2, h.1, h.0);
+    let halves = reverse_halves(Halves(0x102, 0x304, 0x506));
+    assert_eq!(halves.0 as u8, 6);
+    assert_eq!(halves.1 as u8, 4);
+    assert_eq!(halves.2 as u8, 2);
+    let add_meter: fn(Meters) -> Meters = |m| Meters(m.0 + 1);
+    assert_eq!(add_meter(Meters(41)).0, 42);
+    let swap_ints: fn((u8, u32)) -> (u32, u8) = |(a, b)| (b, a);
+    let ints = swap_ints((1, 0x1_0002));
+    assert_eq!(ints.0 as u8, 2);
+    assert_eq!(ints.1, 1);
+    let swap_floats: fn((f32, f64)) -> (f64, f32) = |(a, b)| (b, a);
+    let floats = swap_floats((1.5, 2.5));
+    assert_eq!(floats.0 as u8, 2);
+    assert_eq!(floats.1 as u8, 1);
+    // Pairs of 128bit ints need too many registers to be returned in them
+    let swap_u128s: fn((u128, u128)) -> (u128, u128) = |(a, b)| (b, a);
+    let u128s = swap_u128s((1, 0x1_0000_0000_0000_0000_0002));
+    assert_eq!(u128s.0 as u8, 2);
+    assert_eq!(unsafe { intrinsics::transmute::<u128, [u8; 16]>(u128s.0)[8] }, 1);
+    assert_eq!(u128s.1 as u8, 1);
+    let u128_and_u8: fn(u8) -> (u128, u8) = |a| (a as u128, a + 1);
+    let pair = u128_and_u8(41);
+    assert_eq!(pair.0 as u8, 41);
+    assert_eq!(pair.1, 42);
+    let pass_str: fn(&'static str) -> &'static str = |s| s;
+    let s = pass_str("abc\0");
+    unsafe {
+        assert_eq!(intrinsics::size_of_val(s), 4);
+        assert_eq!(*(s as *const str as *const u8), b'a');
+    }
+    let second_byte: fn(&[u8]) -> u8 = |s| s[1];
+    assert_eq!(second_byte(&[1, 2, 3]), 2);
+
+    let [a, b] = array_subslice([1, 2, 3, 4]);
+    assert_eq!(a, 2);
+    assert_eq!(b, 3);
+
+    let bytes: &[u8] = &[1, 2, 3, 4, 5];
+    let rest = slice_subslice(bytes);
+    assert_eq!(unsafe { intrinsics::size_of_val(rest) }, 4);
+    assert_eq!(rest[0], 2);
+    assert_eq!(rest[3], 5);
+    let middle = slice_middle(bytes);
+    assert_eq!(unsafe { intrinsics::size_of_val(middle) }, 3);
+    assert_eq!(middle[0], 2);
+    assert_eq!(middle[2], 4);
+    assert_eq!(slice_last(bytes), 5);
+    assert_eq!(slice_last(&[7]), 7);
+
+    // The overflow flag of checked binops, computed at runtime. Overflowing shifts abort, so they
+    // are checked by test.sh using example/shl_overflow.rs.
+    let add_u8: fn(u8, u8) -> (u8, bool) = |a, b| unsafe { intrinsics::add_with_overflow(a, b) };
+    let add_i8: fn(i8, i8) -> (i8, bool) = |a, b| unsafe { intrinsics::add_with_overflow(a, b) };
+    let sub_u8: fn(u8, u8) -> (u8, bool) = |a, b| unsafe { intrinsics::sub_with_overflow(a, b) };
+    let sub_i8: fn(i8, i8) -> (i8, bool) = |a, b| unsafe { intrinsics::sub_with_overflow(a, b) };
+    let mul_u8: fn(u8, u8) -> (u8, bool) = |a, b| unsafe { intrinsics::mul_with_overflow(a, b) };
+    let mul_i8: fn(i8, i8) -> (i8, bool) = |a, b| unsafe { intrinsics::mul_with_overflow(a, b) };
+    let mul_u64: fn(u64, u64) -> (u64, bool) = |a, b| unsafe { intrinsics::mul_with_overflow(a, b) };
+    let mul_i64: fn(i64, i64) -> (i64, bool) = |a, b| unsafe { intrinsics::mul_with_overflow(a, b) };
+    assert_eq!(add_u8(200, 55).0, 255);
+    assert!(!add_u8(200, 55).1);
+    assert!(add_u8(200, 56).1);
+    assert_eq!(add_i8(100, 27).0 as u8, 127);
+    assert!(!add_i8(100, 27).1);
+    assert!(add_i8(100, 28).1);
+    assert!(!add_i8(-100, -28).1);
+    assert!(add_i8(-100, -29).1);
+    assert_eq!(sub_u8(5, 5).0, 0);
+    assert!(!sub_u8(5, 5).1);
+    assert!(sub_u8(5, 6).1);
+    assert_eq!(sub_i8(-100, 28).0 as u8, 0x80);
+    assert!(!sub_i8(-100, 28).1);
+    assert!(sub_i8(-100, 29).1);
+    assert!(sub_i8(100, -28).1);
+    assert_eq!(mul_u8(15, 17).0, 255);
+    assert!(!mul_u8(15, 17).1);
+    assert!(mul_u8(16, 16).1);
+    assert_eq!(mul_i8(-64, 2).0 as u8, 0x80);
+    assert!(!mul_i8(-64, 2).1);
+    assert!(mul_i8(64, 2).1);
+    assert!(!mul_u64(0x1_0000_0000, 0xffff_ffff).1);
+    assert!(mul_u64(0x1_0000_0000, 0x1_0000_0000).1);
+    assert!(!mul_i64(-0x4000_0000_0000_0000, 2).1);
+    assert!(mul_i64(0x4000_0000_0000_0000, 2).1);
+    assert!(mul_i64(-0x4000_0000_0000_0000, -2).1);
+    // Shifts which don't overflow would abort too if the overflow flag was set wrongly
+    let shl_i64: fn(i64, u32) -> i64 = |a, b| a << b;
+    assert_eq!(shl_i64(1, 63) as u8, 0);
+    assert_eq!(shl_i64(3, 0) as u8, 3);
+
+    // Float to 128bit int casts saturate
+    let f64_to_i128: fn(f64) -> i128 = |a| a as i128;
+    let f64_to_u128: fn(f64) -> u128 = |a| a as u128;
+    let i128_msb: fn(i128) -> u8 = |a| unsafe { intrinsics::transmute::<i128, [u8; 16]>(a)[15] };
+    let nan: f64 = unsafe { intrinsics::transmute(0x7ff8_0000_0000_0000u64) };
+    assert_eq!(f64_to_i128(42.5) as u8, 42);
+    assert_eq!(i128_msb(f64_to_i128(1e40)), 0x7f);
+    assert_eq!(f64_to_i128(1e40) as u8, 0xff);
+    assert_eq!(i128_msb(f64_to_i128(-1e40)), 0x80);
+    assert_eq!(f64_to_i128(-1e40) as u8, 0);
+    assert_eq!(f64_to_i128(nan) as u8, 0);
+    assert_eq!(f64_to_u128(-1.0) as u8, 0);
+    assert_eq!(f64_to_u128(1e40) as u8, 0xff);
+    assert_eq!(f64_to_u128(nan) as u8, 0);
+
+    let mut gen = || {
+        let _noisy = NoisyDropInner;
+        yield 1u8;
+        2u8
+    };
+    unsafe {
+        match gen.resume() {
+            GeneratorState::Yielded(1) => {}
+            _ => assert!(false),
+        }
+        match gen.resume() {
+            GeneratorState::Complete(2) => {}
+            _ => assert!(false),
+        }
+    }
+
+    // Drop a generator which is suspended at a yield point
+    {
+        let mut gen = || {
+            let _noisy = NoisyDropInner;
+            let _count = CountDrop;
+            yield 1u8;
+        };
+        unsafe {
+            gen.resume();
+        }
+    }
+    assert_eq!(unsafe { DROP_COUNT }, 1);
 }
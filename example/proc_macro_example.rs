@@ -0,0 +1,10 @@
+// Proc macro crate compiled by cg_clif and loaded by a rustc using LLVM, see test.sh
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+
+#[proc_macro_derive(Answer)]
+pub fn derive_answer(_input: TokenStream) -> TokenStream {
+    "fn answer() -> u8 { 42 }".parse().unwrap()
+}
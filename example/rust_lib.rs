@@ -0,0 +1,17 @@
+// Library with a C api, which is built as staticlib and cdylib and used by example/use_rust_lib.c
+
+#![feature(no_core)]
+#![no_core]
+
+extern crate mini_core;
+
+// Not exported from the cdylib, as it isn't `#[no_mangle] pub extern`
+#[inline(never)]
+pub fn add(a: u8, b: u8) -> u8 {
+    a + b
+}
+
+#[no_mangle]
+pub extern "C" fn rust_add(a: u8, b: u8) -> u8 {
+    add(a, b)
+}
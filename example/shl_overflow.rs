@@ -0,0 +1,17 @@
+// Shifting by the bit width of the type must trip the overflow check and abort, see test.sh
+
+#![feature(no_core, start)]
+#![no_core]
+
+extern crate mini_core;
+
+#[link(name = "c")]
+extern "C" {}
+
+#[start]
+fn main(_argc: isize, _argv: *const *const u8) -> isize {
+    // Computed at runtime, so the overflow can't be detected at compile time
+    let shl: fn(u8, u32) -> u8 = |a, b| a << b;
+    shl(1, 7);
+    shl(1, 8) as isize
+}
@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate proc_macro_example;
+
+#[derive(Answer)]
+struct Foo;
+
+fn main() {
+    assert_eq!(answer(), 42);
+}
@@ -0,0 +1,7 @@
+#include <stdint.h>
+
+uint8_t rust_add(uint8_t a, uint8_t b);
+
+int main() {
+    return rust_add(40, 2) == 42 ? 0 : 1;
+}
@@ -0,0 +1,905 @@
+mod sysv;
+
+use std::iter;
+
+use rustc::hir;
+use rustc_target::spec::abi::Abi;
+
+use cranelift::codegen::ir::ArgumentPurpose;
+
+use crate::prelude::*;
+
+#[derive(Copy, Clone, Debug)]
+enum PassMode {
+    NoPass,
+    ByVal(Type),
+    /// Aggregate with a `ScalarPair` layout passed as its two scalars by the "Rust" abi
+    ByValPair(Type, Type),
+    /// Aggregate passed in one or two registers as classified by the SysV "C" abi, or aggregate
+    /// with a scalar layout passed like that scalar by the "Rust" abi
+    Cast(Type, Option<Type>),
+    /// Aggregate of the given size and alignment, which the SysV "C" abi passes by copying it to
+    /// the stack argument area
+    ByMemory(u64, u64),
+    ByRef,
+}
+
+impl PassMode {
+    fn get_param_tys(self, tcx: TyCtxt) -> Vec<Type> {
+        match self {
+            PassMode::NoPass => vec![],
+            PassMode::ByVal(clif_type) => vec![clif_type],
+            PassMode::ByValPair(a, b) => vec![a, b],
+            PassMode::Cast(a, b) => iter::once(a).chain(b).collect(),
+            PassMode::ByMemory(size, _) => vec![types::I64; ((size + 7) / 8) as usize],
+            PassMode::ByRef => vec![pointer_ty(tcx)],
+        }
+    }
+}
+
+fn get_pass_mode<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    abi: Abi,
+    ty: Ty<'tcx>,
+    is_return: bool,
+) -> PassMode {
+    let layout = tcx.layout_of(ParamEnv::reveal_all().and(ty)).unwrap();
+    assert!(!layout.is_unsized());
+    if !is_return && layout.is_zst() {
+        // Zero sized arguments are skipped, so that a closure without captures can be called
+        // through a fn pointer
+        PassMode::NoPass
+    } else if let ty::Never = ty.sty {
+        PassMode::NoPass
+    } else if ty.sty == tcx.mk_unit().sty {
+        PassMode::NoPass
+    } else if let Some(ret_ty) = crate::common::clif_type_from_ty(tcx, ty) {
+        PassMode::ByVal(ret_ty)
+    } else if abi == Abi::C {
+        if layout.is_zst() {
+            return PassMode::NoPass;
+        }
+        match self::sysv::classify_arg(tcx, layout) {
+            Some((a, b)) => PassMode::Cast(a, b),
+            // Returned through a pointer passed by the caller
+            None if is_return => PassMode::ByRef,
+            None => PassMode::ByMemory(layout.size.bytes(), layout.align.abi.bytes()),
+        }
+    } else {
+        // Small aggregates are passed in a register, just like LLVM does. Otherwise fn pointers like
+        // `fn(TokenStream) -> TokenStream` can't be shared with code compiled by LLVM.
+        match layout.abi {
+            layout::Abi::Scalar(ref scalar) if layout.size.bytes() <= 8 => {
+                PassMode::Cast(crate::common::scalar_to_clif_type(tcx, scalar), None)
+            }
+            layout::Abi::Aggregate { .. } if layout.size.bytes() <= 8 => {
+                let int_ty = match layout.size.bytes() {
+                    1 => types::I8,
+                    2 => types::I16,
+                    3..=4 => types::I32,
+                    _ => types::I64,
+                };
+                PassMode::Cast(int_ty, None)
+            }
+            layout::Abi::ScalarPair(ref a, ref b) => {
+                let a = crate::common::scalar_to_clif_type(tcx, a);
+                let b = crate::common::scalar_to_clif_type(tcx, b);
+                // Cranelift returns at most three ints in registers, while a 128bit int takes two
+                // of them, so pairs containing one are returned through a pointer like before.
+                if is_return && (a.bits() > 64 || b.bits() > 64) {
+                    PassMode::ByRef
+                } else {
+                    PassMode::ByValPair(a, b)
+                }
+            }
+            _ => PassMode::ByRef,
+        }
+    }
+}
+
+/// Like `get_pass_mode` for all arguments of a function, but with the aggregates that the SysV
+/// "C" abi passes in memory because not enough registers are left for them. See
+/// `sysv::demote_to_memory`.
+fn get_arg_pass_modes<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    abi: Abi,
+    has_return_ptr: bool,
+    arg_tys: &[Ty<'tcx>],
+) -> Vec<PassMode> {
+    let pass_modes = arg_tys
+        .iter()
+        .map(|&ty| get_pass_mode(tcx, abi, ty, false))
+        .collect();
+    if abi == Abi::C {
+        self::sysv::demote_to_memory(tcx, has_return_ptr, arg_tys, pass_modes)
+    } else {
+        pass_modes
+    }
+}
+
+/// Offset of the second scalar of a `ScalarPair` layout
+fn scalar_pair_b_offset<'a, 'tcx: 'a>(tcx: TyCtxt<'a, 'tcx, 'tcx>, layout: TyLayout<'tcx>) -> i32 {
+    match layout.abi {
+        layout::Abi::ScalarPair(ref a, ref b) => {
+            a.value.size(&tcx).align_to(b.value.align(&tcx).abi).bytes() as i32
+        }
+        _ => bug!("{:?} doesn't have a ScalarPair layout", layout.ty),
+    }
+}
+
+fn adjust_arg_for_abi<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    pass_mode: PassMode,
+    arg: CValue<'tcx>,
+) -> (PassMode, Vec<Value>) {
+    let params = match pass_mode {
+        PassMode::NoPass => vec![],
+        PassMode::ByVal(_) => vec![arg.load_value(fx)],
+        PassMode::ByValPair(a, b) => {
+            let addr = arg.force_stack(fx);
+            let b_offset = scalar_pair_b_offset(fx.tcx, arg.layout());
+            vec![
+                fx.bcx.ins().load(a, MemFlags::new(), addr, 0),
+                fx.bcx.ins().load(b, MemFlags::new(), addr, b_offset),
+            ]
+        }
+        PassMode::Cast(a, b) => {
+            let addr = arg.force_stack(fx);
+            self::sysv::load_eightbytes(fx, addr, arg.layout().size.bytes(), (a, b))
+        }
+        PassMode::ByMemory(size, _) => {
+            let addr = arg.force_stack(fx);
+            self::sysv::load_memory_arg(fx, addr, size)
+        }
+        PassMode::ByRef => vec![arg.force_stack(fx)],
+    };
+    (pass_mode, params)
+}
+
+pub fn clif_sig_from_fn_ty<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    fn_ty: Ty<'tcx>,
+) -> Signature {
+    // For variadic functions this is the signature without the variadic arguments. They are
+    // added per call site by `codegen_call_inner`.
+    let sig = ty_fn_sig(tcx, fn_ty);
+    let (call_conv, inputs, output): (CallConv, Vec<Ty>, Ty) = match sig.abi {
+        Abi::Rust => (CallConv::Fast, sig.inputs().to_vec(), sig.output()),
+        Abi::C => (CallConv::SystemV, sig.inputs().to_vec(), sig.output()),
+        Abi::RustCall => {
+            assert_eq!(sig.inputs().len(), 2);
+            let extra_args = match sig.inputs().last().unwrap().sty {
+                ty::Tuple(ref tupled_arguments) => tupled_arguments,
+                _ => bug!("argument to function with \"rust-call\" ABI is not a tuple"),
+            };
+            let mut inputs: Vec<Ty> = vec![sig.inputs()[0]];
+            inputs.extend(extra_args.into_iter());
+            (CallConv::Fast, inputs, sig.output())
+        }
+        Abi::System => bug!("system abi should be selected elsewhere"),
+        Abi::RustIntrinsic => (CallConv::SystemV, sig.inputs().to_vec(), sig.output()),
+        _ => unimplemented!("unsupported abi {:?}", sig.abi),
+    };
+
+    let output_pass_mode = get_pass_mode(tcx, sig.abi, output, true);
+    let return_ptr = match output_pass_mode {
+        // First param is place to put return val
+        PassMode::ByRef => Some((PassMode::ByRef, vec![AbiParam::new(pointer_ty(tcx))])),
+        _ => None,
+    };
+    let inputs = get_arg_pass_modes(tcx, sig.abi, return_ptr.is_some(), &inputs)
+        .into_iter()
+        .map(|pass_mode| {
+            let params = pass_mode
+                .get_param_tys(tcx)
+                .into_iter()
+                .map(AbiParam::new)
+                .collect();
+            (pass_mode, params)
+        });
+    let params = self::sysv::order_params(tcx, return_ptr.into_iter().chain(inputs).collect(), || {
+        AbiParam::new(types::I64)
+    });
+
+    let returns = match output_pass_mode {
+        PassMode::NoPass => vec![],
+        PassMode::ByVal(ret_ty) => vec![AbiParam::new(ret_ty)],
+        PassMode::ByValPair(a, b) => vec![AbiParam::new(a), AbiParam::new(b)],
+        PassMode::Cast(a, b) => iter::once(a).chain(b).map(AbiParam::new).collect(),
+        PassMode::ByMemory(_, _) => bug!("return value passed by memory"),
+        // The "C" abi requires the place to be returned again
+        PassMode::ByRef if sig.abi == Abi::C => vec![AbiParam::new(pointer_ty(tcx))],
+        PassMode::ByRef => vec![],
+    };
+
+    Signature {
+        params,
+        returns,
+        call_conv,
+    }
+}
+
+fn ty_fn_sig<'a, 'tcx>(tcx: TyCtxt<'a, 'tcx, 'tcx>, ty: Ty<'tcx>) -> ty::FnSig<'tcx> {
+    let sig = match ty.sty {
+        ty::FnDef(..) |
+        // Shims currently have type TyFnPtr. Not sure this should remain.
+        ty::FnPtr(_) => ty.fn_sig(tcx),
+        ty::Closure(def_id, substs) => {
+            let sig = substs.closure_sig(def_id, tcx);
+
+            let env_ty = tcx.closure_env_ty(def_id, substs).unwrap();
+            sig.map_bound(|sig| tcx.mk_fn_sig(
+                iter::once(*env_ty.skip_binder()).chain(sig.inputs().iter().cloned()),
+                sig.output(),
+                sig.variadic,
+                sig.unsafety,
+                sig.abi
+            ))
+        }
+        ty::Generator(def_id, substs, _) => {
+            let sig = substs.poly_sig(def_id, tcx);
+
+            let env_region = ty::ReLateBound(ty::INNERMOST, ty::BrEnv);
+            let env_ty = tcx.mk_mut_ref(tcx.mk_region(env_region), ty);
+
+            sig.map_bound(|sig| {
+                let state_did = tcx.lang_items().gen_state().unwrap();
+                let state_adt_ref = tcx.adt_def(state_did);
+                let state_substs = tcx.intern_substs(&[
+                    sig.yield_ty.into(),
+                    sig.return_ty.into(),
+                ]);
+                let ret_ty = tcx.mk_adt(state_adt_ref, state_substs);
+
+                tcx.mk_fn_sig(iter::once(env_ty),
+                    ret_ty,
+                    false,
+                    hir::Unsafety::Normal,
+                    Abi::Rust
+                )
+            })
+        }
+        _ => bug!("unexpected type {:?} to ty_fn_sig", ty)
+    };
+    tcx.normalize_erasing_late_bound_regions(ParamEnv::reveal_all(), &sig)
+}
+
+pub fn get_function_name_and_sig<'a, 'tcx>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    inst: Instance<'tcx>,
+) -> (String, Signature) {
+    assert!(!inst.substs.needs_infer() && !inst.substs.has_param_types());
+    let fn_ty = inst.ty(tcx);
+    let sig = clif_sig_from_fn_ty(tcx, fn_ty);
+    (tcx.symbol_name(inst).as_str().to_string(), sig)
+}
+
+impl<'a, 'tcx: 'a, B: Backend + 'a> FunctionCx<'a, 'tcx, B> {
+    /// Instance must be monomorphized
+    pub fn get_function_id(&mut self, inst: Instance<'tcx>) -> FuncId {
+        let (name, sig) = get_function_name_and_sig(self.tcx, inst);
+        self.module
+            .declare_function(&name, Linkage::Import, &sig)
+            .unwrap()
+    }
+
+    /// Instance must be monomorphized
+    pub fn get_function_ref(&mut self, inst: Instance<'tcx>) -> FuncRef {
+        let func_id = self.get_function_id(inst);
+        self.module
+            .declare_func_in_func(func_id, &mut self.bcx.func)
+    }
+
+    pub fn lib_call(
+        &mut self,
+        name: &str,
+        input_tys: Vec<types::Type>,
+        output_ty: Option<types::Type>,
+        args: &[Value],
+    ) -> Option<Value> {
+        let sig = Signature {
+            params: input_tys.iter().cloned().map(AbiParam::new).collect(),
+            returns: output_ty
+                .map(|output_ty| vec![AbiParam::new(output_ty)])
+                .unwrap_or(Vec::new()),
+            call_conv: CallConv::SystemV,
+        };
+        let func_id = self
+            .module
+            .declare_function(&name, Linkage::Import, &sig)
+            .unwrap();
+        let func_ref = self
+            .module
+            .declare_func_in_func(func_id, &mut self.bcx.func);
+        let call_inst = self.bcx.ins().call(func_ref, args);
+        if output_ty.is_none() {
+            return None;
+        }
+        let results = self.bcx.inst_results(call_inst);
+        assert_eq!(results.len(), 1);
+        Some(results[0])
+    }
+
+    pub fn easy_call(
+        &mut self,
+        name: &str,
+        args: &[CValue<'tcx>],
+        return_ty: Ty<'tcx>,
+    ) -> CValue<'tcx> {
+        let (input_tys, args): (Vec<_>, Vec<_>) = args
+            .into_iter()
+            .map(|arg| {
+                (
+                    self.clif_type(arg.layout().ty).unwrap(),
+                    arg.load_value(self),
+                )
+            })
+            .unzip();
+        let return_layout = self.layout_of(return_ty);
+        let return_ty = if let ty::Tuple(tup) = return_ty.sty {
+            if !tup.is_empty() {
+                bug!("easy_call( (...) -> <non empty tuple> ) is not allowed");
+            }
+            None
+        } else {
+            Some(self.clif_type(return_ty).unwrap())
+        };
+        if let Some(val) = self.lib_call(name, input_tys, return_ty, &args) {
+            CValue::ByVal(val, return_layout)
+        } else {
+            CValue::ByRef(self.bcx.ins().iconst(self.pointer_type, 0), return_layout)
+        }
+    }
+
+    fn self_sig(&self) -> FnSig<'tcx> {
+        ty_fn_sig(self.tcx, self.instance.ty(self.tcx))
+    }
+
+    fn return_type(&self) -> Ty<'tcx> {
+        self.self_sig().output()
+    }
+}
+
+pub fn codegen_fn_prelude<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    start_ebb: Ebb,
+) {
+    let ssa_analyzed = crate::analyze::analyze(fx);
+
+    let ret_layout = fx.layout_of(fx.return_type());
+    let output_pass_mode = get_pass_mode(fx.tcx, fx.self_sig().abi, fx.return_type(), true);
+
+    // The types of the arguments at the abi level, with the spread argument unpacked
+    let abi_arg_tys = fx
+        .mir
+        .args_iter()
+        .flat_map(|local| {
+            let arg_ty = fx.monomorphize(&fx.mir.local_decls[local].ty);
+            match arg_ty.sty {
+                ty::Tuple(ref tys) if Some(local) == fx.mir.spread_arg => tys.to_vec(),
+                _ => vec![arg_ty],
+            }
+        })
+        .collect::<Vec<_>>();
+    let (ret_param, abi_arg_params) = append_params(fx, start_ebb, output_pass_mode, &abi_arg_tys);
+    let mut abi_arg_params = abi_arg_params.into_iter();
+
+    enum ArgKind {
+        Normal(PassMode, Vec<Value>),
+        Spread(Vec<(PassMode, Vec<Value>)>),
+    }
+
+    let func_params = fx
+        .mir
+        .args_iter()
+        .map(|local| {
+            let arg_ty = fx.monomorphize(&fx.mir.local_decls[local].ty);
+
+            // Adapted from https://github.com/rust-lang/rust/blob/145155dc96757002c7b2e9de8489416e2fdbbd57/src/librustc_codegen_llvm/mir/mod.rs#L442-L482
+            if Some(local) == fx.mir.spread_arg {
+                // This argument (e.g. the last argument in the "rust-call" ABI)
+                // is a tuple that was spread at the ABI level and now we have
+                // to reconstruct it into a tuple local variable, from multiple
+                // individual function arguments.
+
+                let tupled_arg_tys = match arg_ty.sty {
+                    ty::Tuple(ref tys) => tys,
+                    _ => bug!("spread argument isn't a tuple?! but {:?}", arg_ty),
+                };
+
+                let mut ebb_params = Vec::new();
+                for _ in tupled_arg_tys.iter() {
+                    ebb_params.push(abi_arg_params.next().unwrap());
+                }
+
+                (local, ArgKind::Spread(ebb_params), arg_ty)
+            } else {
+                let (pass_mode, ebb_params) = abi_arg_params.next().unwrap();
+                (local, ArgKind::Normal(pass_mode, ebb_params), arg_ty)
+            }
+        })
+        .collect::<Vec<(Local, ArgKind, Ty)>>();
+
+    fx.bcx.switch_to_block(start_ebb);
+
+    fx.top_nop = Some(fx.bcx.ins().nop());
+    fx.add_global_comment(format!("ssa {:?}", ssa_analyzed));
+
+    for local in fx.mir.args_iter() {
+        let arg_ty = fx.monomorphize(&fx.mir.local_decls[local].ty);
+        let pass_mode = get_pass_mode(fx.tcx, fx.self_sig().abi, arg_ty, false);
+        fx.add_global_comment(format!("pass {:?}: {:?} {:?}", local, arg_ty, pass_mode));
+    }
+
+    match output_pass_mode {
+        PassMode::NoPass => {
+            let null = fx.bcx.ins().iconst(fx.pointer_type, 0);
+            //unimplemented!("pass mode nopass");
+            fx.local_map.insert(
+                RETURN_PLACE,
+                CPlace::Addr(null, None, fx.layout_of(fx.return_type())),
+            );
+        }
+        PassMode::ByVal(ret_ty) => {
+            fx.bcx.declare_var(mir_var(RETURN_PLACE), ret_ty);
+            fx.local_map
+                .insert(RETURN_PLACE, CPlace::Var(RETURN_PLACE, ret_layout));
+        }
+        PassMode::ByMemory(_, _) => bug!("return value passed by memory"),
+        PassMode::ByValPair(_, _) | PassMode::Cast(_, _) => {
+            let stack_slot = fx.bcx.create_stack_slot(StackSlotData {
+                kind: StackSlotKind::ExplicitSlot,
+                size: ret_layout.size.bytes() as u32,
+                offset: None,
+            });
+            let place = CPlace::from_stack_slot(fx, stack_slot, ret_layout.ty);
+            fx.local_map.insert(RETURN_PLACE, place);
+        }
+        PassMode::ByRef => {
+            fx.local_map.insert(
+                RETURN_PLACE,
+                CPlace::Addr(ret_param.unwrap(), None, ret_layout),
+            );
+        }
+    }
+
+    for (local, arg_kind, ty) in func_params {
+        let layout = fx.layout_of(ty);
+
+        if let ArgKind::Normal(pass_mode, ref ebb_params) = arg_kind {
+            if !ssa_analyzed
+                .get(&local)
+                .unwrap()
+                .contains(crate::analyze::Flags::NOT_SSA)
+            {
+                fx.bcx
+                    .declare_var(mir_var(local), fx.clif_type(ty).unwrap());
+                match pass_mode {
+                    PassMode::NoPass
+                    | PassMode::ByValPair(_, _)
+                    | PassMode::Cast(_, _)
+                    | PassMode::ByMemory(_, _) => {
+                        unreachable!("ssa local {:?} of type {:?}", local, ty)
+                    }
+                    PassMode::ByVal(_) => fx.def_local_var(local, ebb_params[0]),
+                    PassMode::ByRef => {
+                        let val = CValue::ByRef(ebb_params[0], fx.layout_of(ty)).load_value(fx);
+                        fx.def_local_var(local, val);
+                    }
+                }
+                fx.local_map.insert(local, CPlace::Var(local, layout));
+                continue;
+            }
+        }
+
+        let stack_slot = fx.bcx.create_stack_slot(StackSlotData {
+            kind: StackSlotKind::ExplicitSlot,
+            size: layout.size.bytes() as u32,
+            offset: None,
+        });
+
+        let place = CPlace::from_stack_slot(fx, stack_slot, ty);
+
+        match arg_kind {
+            ArgKind::Normal(pass_mode, ebb_params) => {
+                write_param_to_place(fx, pass_mode, ebb_params, place)
+            }
+            ArgKind::Spread(ebb_params) => {
+                for (i, (pass_mode, ebb_params)) in ebb_params.into_iter().enumerate() {
+                    let sub_place = place.place_field(fx, mir::Field::new(i));
+                    write_param_to_place(fx, pass_mode, ebb_params, sub_place);
+                }
+            }
+        }
+        fx.local_map.insert(local, place);
+    }
+
+    for local in fx.mir.vars_and_temps_iter() {
+        let ty = fx.mir.local_decls[local].ty;
+        let layout = fx.layout_of(ty);
+
+        let place = if ssa_analyzed
+            .get(&local)
+            .unwrap()
+            .contains(crate::analyze::Flags::NOT_SSA)
+        {
+            let stack_slot = fx.bcx.create_stack_slot(StackSlotData {
+                kind: StackSlotKind::ExplicitSlot,
+                size: layout.size.bytes() as u32,
+                offset: None,
+            });
+            CPlace::from_stack_slot(fx, stack_slot, ty)
+        } else {
+            fx.bcx
+                .declare_var(mir_var(local), fx.clif_type(ty).unwrap());
+            CPlace::Var(local, layout)
+        };
+
+        fx.local_map.insert(local, place);
+    }
+
+    fx.bcx
+        .ins()
+        .jump(*fx.ebb_map.get(&START_BLOCK).unwrap(), &[]);
+}
+
+/// Appends the ebb params for the return place and the arguments in the order of the signature.
+/// See `sysv::order_params`.
+fn append_params<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    start_ebb: Ebb,
+    output_pass_mode: PassMode,
+    arg_tys: &[Ty<'tcx>],
+) -> (Option<Value>, Vec<(PassMode, Vec<Value>)>) {
+    let return_ptr = match output_pass_mode {
+        PassMode::ByRef => Some((PassMode::ByRef, vec![fx.pointer_type])),
+        _ => None,
+    };
+    let has_return_ptr = return_ptr.is_some();
+    let pass_modes = get_arg_pass_modes(fx.tcx, fx.self_sig().abi, has_return_ptr, arg_tys);
+    let args = return_ptr
+        .into_iter()
+        .chain(
+            pass_modes
+                .iter()
+                .map(|&pass_mode| (pass_mode, pass_mode.get_param_tys(fx.tcx))),
+        )
+        // Remember which argument every param belongs to
+        .enumerate()
+        .map(|(i, (pass_mode, tys))| {
+            (
+                pass_mode,
+                tys.into_iter().map(|ty| Some((i, ty))).collect(),
+            )
+        })
+        .collect();
+
+    let mut params = vec![Vec::new(); arg_tys.len() + has_return_ptr as usize];
+    for param in self::sysv::order_params(fx.tcx, args, || None) {
+        match param {
+            Some((i, ty)) => params[i].push(fx.bcx.append_ebb_param(start_ebb, ty)),
+            None => {
+                fx.bcx.append_ebb_param(start_ebb, types::I64);
+            }
+        }
+    }
+
+    let return_ptr = if has_return_ptr {
+        Some(params.remove(0)[0])
+    } else {
+        None
+    };
+    (return_ptr, pass_modes.into_iter().zip(params).collect())
+}
+
+fn write_param_to_place<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    pass_mode: PassMode,
+    ebb_params: Vec<Value>,
+    place: CPlace<'tcx>,
+) {
+    match pass_mode {
+        PassMode::NoPass => assert!(ebb_params.is_empty()),
+        PassMode::ByVal(_) => place.write_cvalue(fx, CValue::ByVal(ebb_params[0], place.layout())),
+        PassMode::ByValPair(_, _) => {
+            let b_offset = scalar_pair_b_offset(fx.tcx, place.layout());
+            let addr = place.expect_addr();
+            fx.bcx.ins().store(MemFlags::new(), ebb_params[0], addr, 0);
+            fx.bcx.ins().store(MemFlags::new(), ebb_params[1], addr, b_offset);
+        }
+        PassMode::Cast(_, _) | PassMode::ByMemory(_, _) => {
+            let size = place.layout().size.bytes();
+            self::sysv::store_eightbytes(fx, place.expect_addr(), size, &ebb_params);
+        }
+        PassMode::ByRef => place.write_cvalue(fx, CValue::ByRef(ebb_params[0], place.layout())),
+    }
+}
+
+pub fn codegen_terminator_call<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    func: &Operand<'tcx>,
+    args: &[Operand<'tcx>],
+    destination: &Option<(Place<'tcx>, BasicBlock)>,
+) {
+    let fn_ty = fx.monomorphize(&func.ty(fx.mir, fx.tcx));
+    let sig = ty_fn_sig(fx.tcx, fn_ty);
+
+    // Unpack arguments tuple for closures
+    let args = if sig.abi == Abi::RustCall {
+        assert_eq!(args.len(), 2, "rust-call abi requires two arguments");
+        let self_arg = trans_operand(fx, &args[0]);
+        let pack_arg = trans_operand(fx, &args[1]);
+        let mut args = Vec::new();
+        args.push(self_arg);
+        match pack_arg.layout().ty.sty {
+            ty::Tuple(ref tupled_arguments) => {
+                for (i, _) in tupled_arguments.iter().enumerate() {
+                    args.push(pack_arg.value_field(fx, mir::Field::new(i)));
+                }
+            }
+            _ => bug!("argument to function with \"rust-call\" ABI is not a tuple"),
+        }
+        args
+    } else {
+        args.into_iter()
+            .map(|arg| trans_operand(fx, arg))
+            .collect::<Vec<_>>()
+    };
+
+    let destination = destination
+        .as_ref()
+        .map(|&(ref place, bb)| (trans_place(fx, place), bb));
+
+    if let ty::FnDef(def_id, substs) = fn_ty.sty {
+        let sig = ty_fn_sig(fx.tcx, fn_ty);
+
+        if sig.abi == Abi::RustIntrinsic {
+            crate::intrinsics::codegen_intrinsic_call(fx, def_id, substs, args, destination);
+            return;
+        }
+    }
+
+    codegen_call_inner(
+        fx,
+        Some(func),
+        fn_ty,
+        args,
+        destination.map(|(place, _)| place),
+    );
+
+    if let Some((_, dest)) = destination {
+        let ret_ebb = fx.get_ebb(dest);
+        fx.bcx.ins().jump(ret_ebb, &[]);
+    } else {
+        trap_unreachable(&mut fx.bcx);
+    }
+}
+
+pub fn codegen_call_inner<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    func: Option<&Operand<'tcx>>,
+    fn_ty: Ty<'tcx>,
+    args: Vec<CValue<'tcx>>,
+    ret_place: Option<CPlace<'tcx>>,
+) {
+    let sig = ty_fn_sig(fx.tcx, fn_ty);
+
+    if sig.variadic {
+        // rustc rejects variadic arguments which would need the default argument promotions of C
+        for arg in &args[sig.inputs().len()..] {
+            debug_assert!(
+                match arg.layout().ty.sty {
+                    ty::Float(FloatTy::F32)
+                    | ty::Bool
+                    | ty::Uint(UintTy::U8)
+                    | ty::Uint(UintTy::U16)
+                    | ty::Int(IntTy::I8)
+                    | ty::Int(IntTy::I16) => false,
+                    _ => true,
+                },
+                "Unpromoted variadic argument of type {:?}",
+                arg.layout().ty
+            );
+        }
+    }
+
+    let ret_layout = fx.layout_of(sig.output());
+
+    let output_pass_mode = get_pass_mode(fx.tcx, sig.abi, sig.output(), true);
+    let return_ptr = match output_pass_mode {
+        PassMode::NoPass => None,
+        PassMode::ByRef => match ret_place {
+            Some(ret_place) => Some(ret_place.expect_addr()),
+            None => Some(fx.bcx.ins().iconst(fx.pointer_type, 0)),
+        },
+        PassMode::ByVal(_) | PassMode::ByValPair(_, _) | PassMode::Cast(_, _) => None,
+        PassMode::ByMemory(_, _) => bug!("return value passed by memory"),
+    };
+
+    let instance = match fn_ty.sty {
+        ty::FnDef(def_id, substs) => {
+            Some(Instance::resolve(fx.tcx, ParamEnv::reveal_all(), def_id, substs).unwrap())
+        }
+        _ => None,
+    };
+
+    if let Some(Instance { def: InstanceDef::DropGlue(_, None), .. }) = instance {
+        // Empty drop glue is a nop and is not collected as mono item.
+        return;
+    }
+
+    let arg_tys = args.iter().map(|arg| arg.layout().ty).collect::<Vec<_>>();
+    let pass_modes = get_arg_pass_modes(fx.tcx, sig.abi, return_ptr.is_some(), &arg_tys);
+
+    let func_ref: Option<Value>; // Indirect call target
+
+    let first_arg = {
+        if let Some(Instance {
+            def: InstanceDef::Virtual(_, idx),
+            ..
+        }) = instance
+        {
+            let (ptr, method) = crate::vtable::get_ptr_and_method_ref(fx, args[0], idx);
+            func_ref = Some(method);
+            Some((PassMode::ByVal(fx.pointer_type), vec![ptr]))
+        } else {
+            func_ref = if instance.is_none() {
+                let func = trans_operand(fx, func.expect("indirect call without func Operand"));
+                Some(func.load_value(fx))
+            } else {
+                None
+            };
+
+            args.get(0)
+                .map(|arg| adjust_arg_for_abi(fx, pass_modes[0], *arg))
+        }
+    };
+
+    let variadic_arg_tys = if sig.variadic {
+        let in_memory = pass_modes.iter().any(|pass_mode| match pass_mode {
+            PassMode::ByMemory(_, _) => true,
+            _ => false,
+        });
+        if in_memory {
+            unimpl!("Passing structs in memory to variadic functions is not yet supported");
+        }
+        pass_modes[sig.inputs().len()..]
+            .iter()
+            .flat_map(|pass_mode| pass_mode.get_param_tys(fx.tcx))
+            .collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+
+    let call_args = return_ptr
+        .map(|return_ptr| (PassMode::ByRef, vec![return_ptr]))
+        .into_iter()
+        .chain(first_arg)
+        .chain(
+            args.into_iter()
+                .zip(pass_modes)
+                .skip(1)
+                .map(|(arg, pass_mode)| adjust_arg_for_abi(fx, pass_mode, arg)),
+        )
+        .collect::<Vec<_>>();
+    let mut call_args = self::sysv::order_params(fx.tcx, call_args, || {
+        fx.bcx.ins().iconst(types::I64, 0)
+    });
+
+    let mut clif_sig = clif_sig_from_fn_ty(fx.tcx, fn_ty);
+    if let Some(InstanceDef::Virtual(_, _)) = instance.map(|instance| instance.def) {
+        // The method of the concrete type takes a thin pointer instead of the fat pointer in the
+        // signature of the trait method
+        let receiver_idx = return_ptr.is_some() as usize;
+        let receiver_param_count = get_pass_mode(fx.tcx, sig.abi, sig.inputs()[0], false)
+            .get_param_tys(fx.tcx)
+            .len();
+        clif_sig.params.splice(
+            receiver_idx..receiver_idx + receiver_param_count,
+            iter::once(AbiParam::new(fx.pointer_type)),
+        );
+    }
+    let func_ref = if sig.variadic {
+        // Every call site gets its own signature containing the types of the actual arguments
+        clif_sig
+            .params
+            .extend(variadic_arg_tys.into_iter().map(AbiParam::new));
+
+        // %al contains an upper bound of the amount of vector registers used for arguments
+        let float_arg_count = clif_sig
+            .params
+            .iter()
+            .filter(|param| param.value_type.is_float())
+            .count()
+            .min(8);
+        let rax = fx.module.isa().register_info().parse_regunit("rax").unwrap();
+        clif_sig.params.push(AbiParam::special_reg(
+            types::I64,
+            ArgumentPurpose::Normal,
+            rax,
+        ));
+        call_args.push(fx.bcx.ins().iconst(types::I64, float_arg_count as i64));
+
+        // The function is declared with the signature without variadic arguments, so call it
+        // indirectly to be able to use the signature of this call site
+        func_ref.or_else(|| {
+            let func_ref =
+                fx.get_function_ref(instance.expect("non-indirect call on non-FnDef type"));
+            Some(fx.bcx.ins().func_addr(fx.pointer_type, func_ref))
+        })
+    } else {
+        func_ref
+    };
+
+    let sig = fx.bcx.import_signature(clif_sig);
+    let call_inst = if let Some(func_ref) = func_ref {
+        fx.bcx.ins().call_indirect(sig, func_ref, &call_args)
+    } else {
+        let func_ref = fx.get_function_ref(instance.expect("non-indirect call on non-FnDef type"));
+        fx.bcx.ins().call(func_ref, &call_args)
+    };
+
+    match output_pass_mode {
+        PassMode::NoPass => {}
+        PassMode::ByVal(_) => {
+            if let Some(ret_place) = ret_place {
+                let results = fx.bcx.inst_results(call_inst);
+                ret_place.write_cvalue(fx, CValue::ByVal(results[0], ret_layout));
+            }
+        }
+        PassMode::ByValPair(_, _) => {
+            if let Some(ret_place) = ret_place {
+                let results = fx.bcx.inst_results(call_inst).to_vec();
+                let b_offset = scalar_pair_b_offset(fx.tcx, ret_layout);
+                let addr = ret_place.expect_addr();
+                fx.bcx.ins().store(MemFlags::new(), results[0], addr, 0);
+                fx.bcx.ins().store(MemFlags::new(), results[1], addr, b_offset);
+            }
+        }
+        PassMode::Cast(_, _) => {
+            if let Some(ret_place) = ret_place {
+                let results = fx.bcx.inst_results(call_inst).to_vec();
+                let size = ret_layout.size.bytes();
+                self::sysv::store_eightbytes(fx, ret_place.expect_addr(), size, &results);
+            }
+        }
+        PassMode::ByMemory(_, _) => bug!("return value passed by memory"),
+        PassMode::ByRef => {}
+    }
+}
+
+pub fn codegen_return(fx: &mut FunctionCx<impl Backend>) {
+    match get_pass_mode(fx.tcx, fx.self_sig().abi, fx.return_type(), true) {
+        PassMode::NoPass => {
+            fx.bcx.ins().return_(&[]);
+        }
+        PassMode::ByRef => {
+            if fx.self_sig().abi == Abi::C {
+                let ret_ptr = fx.get_local_place(RETURN_PLACE).expect_addr();
+                fx.bcx.ins().return_(&[ret_ptr]);
+            } else {
+                fx.bcx.ins().return_(&[]);
+            }
+        }
+        PassMode::ByVal(_) => {
+            let place = fx.get_local_place(RETURN_PLACE);
+            let ret_val = place.to_cvalue(fx).load_value(fx);
+            fx.bcx.ins().return_(&[ret_val]);
+        }
+        PassMode::ByValPair(a, b) => {
+            let place = fx.get_local_place(RETURN_PLACE);
+            let b_offset = scalar_pair_b_offset(fx.tcx, place.layout());
+            let addr = place.expect_addr();
+            let a = fx.bcx.ins().load(a, MemFlags::new(), addr, 0);
+            let b = fx.bcx.ins().load(b, MemFlags::new(), addr, b_offset);
+            fx.bcx.ins().return_(&[a, b]);
+        }
+        PassMode::Cast(a, b) => {
+            let place = fx.get_local_place(RETURN_PLACE);
+            let size = place.layout().size.bytes();
+            let ret_vals = self::sysv::load_eightbytes(fx, place.expect_addr(), size, (a, b));
+            fx.bcx.ins().return_(&ret_vals);
+        }
+        PassMode::ByMemory(_, _) => bug!("return value passed by memory"),
+    }
+}
@@ -0,0 +1,330 @@
+//! Classification of aggregates for the x86_64 System V "C" abi
+//!
+//! See librustc_target/abi/call/x86_64.rs for reference
+
+use super::PassMode;
+use crate::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Class {
+    Int,
+    Sse,
+}
+
+const MAX_EIGHTBYTES: usize = 2;
+/// Number of integer registers used for arguments
+const ARG_GPRS: usize = 6;
+/// Number of vector registers used for arguments
+const ARG_FPRS: usize = 8;
+
+/// Returns `Err(())` when the value has to be passed in memory.
+fn classify<'a, 'tcx: 'a>(
+    cx: &layout::LayoutCx<'tcx, TyCtxt<'a, 'tcx, 'tcx>>,
+    layout: TyLayout<'tcx>,
+    cls: &mut [Option<Class>],
+    off: Size,
+) -> Result<(), ()> {
+    if off.bytes() % layout.align.abi.bytes() != 0 {
+        // Unaligned fields force the whole value into memory
+        return if layout.is_zst() { Ok(()) } else { Err(()) };
+    }
+
+    let c = match layout.abi {
+        Abi::Uninhabited => return Ok(()),
+        Abi::Scalar(ref scalar) => match scalar.value {
+            layout::Int(..) | layout::Pointer => Class::Int,
+            layout::Float(_) => Class::Sse,
+        },
+        // SIMD is not yet supported
+        Abi::Vector { .. } => return Err(()),
+        Abi::ScalarPair(..) | Abi::Aggregate { .. } => match layout.variants {
+            layout::Variants::Single { .. } => {
+                for i in 0..layout.fields.count() {
+                    let field_off = off + layout.fields.offset(i);
+                    classify(cx, layout.field(cx, i).unwrap(), cls, field_off)?;
+                }
+                return Ok(());
+            }
+            layout::Variants::Tagged { .. } | layout::Variants::NicheFilling { .. } => {
+                return Err(())
+            }
+        },
+    };
+
+    let first = (off.bytes() / 8) as usize;
+    let last = ((off.bytes() + layout.size.bytes() - 1) / 8) as usize;
+    for cls in &mut cls[first..=last] {
+        // An eightbyte containing both integers and floats is passed in an integer register
+        if *cls != Some(Class::Int) {
+            *cls = Some(c);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the types of the registers used to pass an aggregate, or `None` when it has to be
+/// passed in memory.
+pub fn classify_arg<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    layout: TyLayout<'tcx>,
+) -> Option<(Type, Option<Type>)> {
+    let size = layout.size.bytes();
+    if size > (MAX_EIGHTBYTES * 8) as u64 {
+        return None;
+    }
+
+    let cx = layout::LayoutCx {
+        tcx,
+        param_env: ParamEnv::reveal_all(),
+    };
+    let mut cls = [None; MAX_EIGHTBYTES];
+    if classify(&cx, layout, &mut cls, Size::ZERO).is_err() {
+        return None;
+    }
+
+    let eightbytes = ((size + 7) / 8) as usize;
+    let mut tys = cls[..eightbytes].iter().enumerate().map(|(i, cls)| {
+        let remaining = size - i as u64 * 8;
+        match cls.unwrap_or(Class::Int) {
+            Class::Int => match remaining {
+                1 => types::I8,
+                2 => types::I16,
+                3 | 4 => types::I32,
+                _ => types::I64,
+            },
+            Class::Sse if remaining <= 4 => types::F32,
+            Class::Sse => types::F64,
+        }
+    });
+    let first = tys.next().unwrap();
+    Some((first, tys.next()))
+}
+
+/// Integer and vector registers left for passing arguments
+#[derive(Clone, Copy, Debug)]
+struct FreeRegs {
+    gprs: usize,
+    fprs: usize,
+}
+
+impl FreeRegs {
+    fn new() -> Self {
+        FreeRegs {
+            gprs: ARG_GPRS,
+            fprs: ARG_FPRS,
+        }
+    }
+
+    /// Takes the registers for the given params. When there are not enough registers left for all
+    /// of them, no register is taken and `false` is returned, as the argument has to be passed on
+    /// the stack as a whole.
+    fn take(&mut self, tys: &[Type]) -> bool {
+        let fprs = tys.iter().filter(|ty| ty.is_float()).count();
+        let gprs = tys
+            .iter()
+            .filter(|ty| !ty.is_float())
+            .map(|ty| (ty.bytes() as usize + 7) / 8)
+            .sum::<usize>();
+        if gprs > self.gprs || fprs > self.fprs {
+            return false;
+        }
+        self.gprs -= gprs;
+        self.fprs -= fprs;
+        true
+    }
+}
+
+/// Passes aggregates classified as two eightbytes in memory when there are not enough registers
+/// left for both eightbytes. Cranelift would split them between a register and the stack
+/// otherwise.
+pub(super) fn demote_to_memory<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    has_return_ptr: bool,
+    arg_tys: &[Ty<'tcx>],
+    pass_modes: Vec<PassMode>,
+) -> Vec<PassMode> {
+    let mut regs = FreeRegs::new();
+    if has_return_ptr {
+        regs.take(&[pointer_ty(tcx)]);
+    }
+    arg_tys
+        .iter()
+        .zip(pass_modes)
+        .map(|(&ty, pass_mode)| match pass_mode {
+            PassMode::ByMemory(_, _) => pass_mode,
+            PassMode::Cast(_, Some(_)) if !regs.take(&pass_mode.get_param_tys(tcx)) => {
+                let layout = tcx.layout_of(ParamEnv::reveal_all().and(ty)).unwrap();
+                PassMode::ByMemory(layout.size.bytes(), layout.align.abi.bytes())
+            }
+            _ => {
+                regs.take(&pass_mode.get_param_tys(tcx));
+                pass_mode
+            }
+        })
+        .collect()
+}
+
+/// Orders the params of the arguments of a function for its Cranelift signature. `pad` creates an
+/// integer param which is ignored by the callee.
+///
+/// Aggregates passed in memory are copied to the stack argument area, even when there are
+/// registers left. Cranelift only passes params on the stack once all registers of their kind
+/// are used, so the params of all arguments passed on the stack are moved after the params of
+/// the arguments passed in registers and after padding params using up the remaining integer
+/// registers. Arguments which don't fit in the remaining registers keep their position relative
+/// to the aggregates passed in memory that way.
+pub(super) fn order_params<T>(
+    tcx: TyCtxt,
+    args: Vec<(PassMode, Vec<T>)>,
+    mut pad: impl FnMut() -> T,
+) -> Vec<T> {
+    let in_memory = |pass_mode: &PassMode| match pass_mode {
+        PassMode::ByMemory(_, _) => true,
+        _ => false,
+    };
+    if !args.iter().any(|(pass_mode, _)| in_memory(pass_mode)) {
+        return args.into_iter().flat_map(|(_, params)| params).collect();
+    }
+
+    let mut regs = FreeRegs::new();
+    let mut reg_params = Vec::new();
+    let mut stack_params = Vec::new();
+    let mut stack_offset = 0;
+    for (pass_mode, params) in args {
+        let param_tys = pass_mode.get_param_tys(tcx);
+        if !in_memory(&pass_mode) && regs.take(&param_tys) {
+            reg_params.extend(params);
+            continue;
+        }
+
+        // A float only ends up here once all vector registers are used, so Cranelift passes it
+        // on the stack too.
+        let (size, align) = match pass_mode {
+            PassMode::ByMemory(size, align) => (size, align),
+            _ => {
+                let size = param_tys.iter().map(|ty| u64::from(ty.bytes())).sum::<u64>();
+                (size, size)
+            }
+        };
+        let align = align.max(8).min(16);
+        while stack_offset % align != 0 {
+            stack_params.push(pad());
+            stack_offset += 8;
+        }
+        stack_offset += (size + 7) / 8 * 8;
+        stack_params.extend(params);
+    }
+
+    for _ in 0..regs.gprs {
+        reg_params.push(pad());
+    }
+    reg_params.extend(stack_params);
+    reg_params
+}
+
+/// Loads an aggregate passed in memory as eightbytes. See `order_params`.
+pub fn load_memory_arg<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    addr: Value,
+    size: u64,
+) -> Vec<Value> {
+    (0..(size + 7) / 8)
+        .map(|i| load_partial(fx, types::I64, addr, i * 8, (size - i * 8).min(8)))
+        .collect()
+}
+
+/// Loads the eightbytes of an aggregate from memory. The last eightbyte may only be partially
+/// used, so it is loaded in pieces to avoid reading past the end of the aggregate.
+pub fn load_eightbytes<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    addr: Value,
+    size: u64,
+    tys: (Type, Option<Type>),
+) -> Vec<Value> {
+    let (a, b) = tys;
+    let mut vals = vec![load_partial(fx, a, addr, 0, size.min(8))];
+    if let Some(b) = b {
+        vals.push(load_partial(fx, b, addr, 8, size - 8));
+    }
+    vals
+}
+
+/// Stores the eightbytes of an aggregate to memory. See `load_eightbytes`.
+pub fn store_eightbytes<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    addr: Value,
+    size: u64,
+    vals: &[Value],
+) {
+    for (i, &val) in vals.iter().enumerate() {
+        let offset = i as u64 * 8;
+        store_partial(fx, val, addr, offset, (size - offset).min(8));
+    }
+}
+
+fn piece_size(remaining: u64) -> u64 {
+    if remaining >= 4 {
+        4
+    } else if remaining >= 2 {
+        2
+    } else {
+        1
+    }
+}
+
+fn load_partial<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    ty: Type,
+    addr: Value,
+    offset: u64,
+    size: u64,
+) -> Value {
+    if size == u64::from(ty.bytes()) {
+        return fx.bcx.ins().load(ty, MemFlags::new(), addr, offset as i32);
+    }
+
+    assert!(ty.is_int(), "partial load of {:?}", ty);
+    let mut val = fx.bcx.ins().iconst(ty, 0);
+    let mut done = 0;
+    while done < size {
+        let piece = piece_size(size - done);
+        let piece_ty = Type::int((piece * 8) as u16).unwrap();
+        let piece_val = fx
+            .bcx
+            .ins()
+            .load(piece_ty, MemFlags::new(), addr, (offset + done) as i32);
+        let piece_val = fx.bcx.ins().uextend(ty, piece_val);
+        let piece_val = fx.bcx.ins().ishl_imm(piece_val, (done * 8) as i64);
+        val = fx.bcx.ins().bor(val, piece_val);
+        done += piece;
+    }
+    val
+}
+
+fn store_partial<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    val: Value,
+    addr: Value,
+    offset: u64,
+    size: u64,
+) {
+    let ty = fx.bcx.func.dfg.value_type(val);
+    if size == u64::from(ty.bytes()) {
+        fx.bcx.ins().store(MemFlags::new(), val, addr, offset as i32);
+        return;
+    }
+
+    assert!(ty.is_int(), "partial store of {:?}", ty);
+    let mut done = 0;
+    while done < size {
+        let piece = piece_size(size - done);
+        let piece_ty = Type::int((piece * 8) as u16).unwrap();
+        let piece_val = fx.bcx.ins().ushr_imm(val, (done * 8) as i64);
+        let piece_val = fx.bcx.ins().ireduce(piece_ty, piece_val);
+        fx.bcx
+            .ins()
+            .store(MemFlags::new(), piece_val, addr, (offset + done) as i32);
+        done += piece;
+    }
+}
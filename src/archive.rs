@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 use crate::prelude::*;
 
@@ -11,11 +12,16 @@ pub struct ArchiveConfig<'a> {
     pub lib_search_paths: Vec<PathBuf>,
 }
 
+enum ArchiveEntry {
+    File(PathBuf),
+    Data(Vec<u8>),
+}
+
 pub struct ArchiveBuilder<'a> {
     cfg: ArchiveConfig<'a>,
     src_archive: Option<ar::Archive<File>>,
     src_entries: HashMap<String, usize>,
-    builder: ar::Builder<File>,
+    additions: Vec<(String, ArchiveEntry)>,
     update_symbols: bool,
 }
 
@@ -40,13 +46,11 @@ impl<'a> ArchiveBuilder<'a> {
             (None, HashMap::new())
         };
 
-        let builder = ar::Builder::new(File::create(&cfg.dst).unwrap());
-
         ArchiveBuilder {
             cfg,
             src_archive,
             src_entries,
-            builder,
+            additions: Vec::new(),
             update_symbols: false,
         }
     }
@@ -62,28 +66,113 @@ impl<'a> ArchiveBuilder<'a> {
         );
     }
 
+    pub fn add_file(&mut self, file: &Path) {
+        self.additions.push((
+            file.file_name().unwrap().to_str().unwrap().to_string(),
+            ArchiveEntry::File(file.to_path_buf()),
+        ));
+    }
+
+    /// Add all object files of the given rlib. Rust objects are skipped when `lto` is true, as
+    /// they are already part of the LTO module. Native objects are skipped when `skip_objects`
+    /// is true.
+    pub fn add_rlib(
+        &mut self,
+        rlib: &Path,
+        name: &str,
+        lto: bool,
+        skip_objects: bool,
+    ) -> io::Result<()> {
+        let obj_start = name.to_owned();
+        self.add_archive(rlib, move |fname: &str| {
+            // Ignore metadata and bytecode files, no matter the name.
+            if fname == crate::metadata::METADATA_FILENAME
+                || fname.ends_with(crate::link_copied::RLIB_BYTECODE_EXTENSION)
+            {
+                return true;
+            }
+
+            // Don't include Rust objects if LTO is enabled
+            let is_rust_object = fname.starts_with(&obj_start) && fname.ends_with(".o");
+            if lto && is_rust_object {
+                return true;
+            }
+
+            // Otherwise if this is *not* a rust object and we're skipping
+            // objects then skip this file
+            skip_objects && !is_rust_object
+        })
+    }
+
+    /// Add all files of the native static library with the given name.
+    pub fn add_native_library(&mut self, name: &str) {
+        let file_name = format!("lib{}.a", name);
+        let location = self
+            .cfg
+            .lib_search_paths
+            .iter()
+            .map(|dir| dir.join(&file_name))
+            .find(|path| path.exists());
+        let location = match location {
+            Some(location) => location,
+            None => self.cfg.sess.fatal(&format!(
+                "could not find native static library `{}`, perhaps an -L flag is missing?",
+                name
+            )),
+        };
+        if let Err(e) = self.add_archive(&location, |_| false) {
+            self.cfg.sess.fatal(&format!(
+                "failed to add native library {}: {}",
+                location.to_string_lossy(),
+                e
+            ));
+        }
+    }
+
+    fn add_archive(&mut self, archive: &Path, skip: impl Fn(&str) -> bool) -> io::Result<()> {
+        let mut archive = ar::Archive::new(File::open(archive)?);
+        while let Some(entry) = archive.next_entry() {
+            let mut entry = entry?;
+            let file_name = String::from_utf8(entry.header().identifier().to_vec())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if skip(&file_name) {
+                continue;
+            }
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            self.additions.push((file_name, ArchiveEntry::Data(data)));
+        }
+        Ok(())
+    }
+
     pub fn update_symbols(&mut self) {
         self.update_symbols = true;
     }
 
-    pub fn build(mut self) {
+    pub fn build(self) {
+        let mut members = Vec::new();
+
         // Add files from original archive
         if let Some(mut src_archive) = self.src_archive {
-            for (_entry_name, entry_idx) in self.src_entries.into_iter() {
-                let entry = src_archive.jump_to_entry(entry_idx).unwrap();
-                let orig_header = entry.header();
-                let mut header =
-                    ar::Header::new(orig_header.identifier().to_vec(), orig_header.size());
-                header.set_mtime(orig_header.mtime());
-                header.set_uid(orig_header.uid());
-                header.set_gid(orig_header.gid());
-                header.set_mode(orig_header.mode());
-                self.builder.append(&header, entry).unwrap();
+            for (entry_name, entry_idx) in self.src_entries.into_iter() {
+                let mut entry = src_archive.jump_to_entry(entry_idx).unwrap();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                members.push((entry_name, data));
+            }
+        }
+
+        // Add newly added files
+        for (entry_name, entry) in self.additions.into_iter() {
+            match entry {
+                ArchiveEntry::File(file) => {
+                    members.push((entry_name, std::fs::read(file).unwrap()));
+                }
+                ArchiveEntry::Data(data) => members.push((entry_name, data)),
             }
         }
 
-        // Finalize archive
-        std::mem::drop(self.builder);
+        write_gnu_archive(&self.cfg.dst, &members).unwrap();
 
         // Run ranlib to be able to link the archive
         let status = std::process::Command::new("ranlib")
@@ -97,3 +186,55 @@ impl<'a> ArchiveBuilder<'a> {
         );
     }
 }
+
+/// Writes an archive in the format of GNU ar. `ar::Builder` writes BSD style long member names,
+/// which GNU ranlib and ld don't understand.
+pub fn write_gnu_archive(dst: &Path, members: &[(String, Vec<u8>)]) -> io::Result<()> {
+    let mut long_names = Vec::new();
+    let identifiers = members
+        .iter()
+        .map(|(name, _)| {
+            if name.len() < 16 {
+                format!("{}/", name)
+            } else {
+                let identifier = format!("/{}", long_names.len());
+                long_names.extend_from_slice(name.as_bytes());
+                long_names.extend_from_slice(b"/\n");
+                identifier
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut archive = Vec::new();
+    archive.extend_from_slice(b"!<arch>\n");
+    if !long_names.is_empty() {
+        write_gnu_member(&mut archive, "//", &long_names)?;
+    }
+    for (identifier, (_, data)) in identifiers.iter().zip(members) {
+        write_gnu_member(&mut archive, identifier, data)?;
+    }
+    std::fs::write(dst, archive)
+}
+
+fn write_gnu_member(archive: &mut Vec<u8>, identifier: &str, data: &[u8]) -> io::Result<()> {
+    if identifier == "//" {
+        // The long name table has no mtime, owner or mode
+        write!(archive, "{:<48}{:<10}`\n", identifier, data.len())?;
+    } else {
+        write!(
+            archive,
+            "{:<16}{:<12}{:<6}{:<6}{:<8o}{:<10}`\n",
+            identifier,
+            0,
+            0,
+            0,
+            0o644,
+            data.len()
+        )?;
+    }
+    archive.extend_from_slice(data);
+    if data.len() % 2 != 0 {
+        archive.push(b'\n');
+    }
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::emit_pool::PendingFunction;
 use crate::prelude::*;
 
 struct PrintOnPanic<F: Fn() -> String>(F);
@@ -14,7 +15,11 @@ pub fn trans_mono_item<'a, 'tcx: 'a>(
     module: &mut Module<impl Backend>,
     caches: &mut Caches<'tcx>,
     ccx: &mut crate::constant::ConstantCx,
+    debug_context: Option<&mut DebugContext<'tcx>>,
+    pending_functions: Option<&mut Vec<PendingFunction>>,
+    global_asm: &mut String,
     mono_item: MonoItem<'tcx>,
+    linkage: Linkage,
 ) {
     match mono_item {
         MonoItem::Fn(inst) => {
@@ -44,14 +49,24 @@ pub fn trans_mono_item<'a, 'tcx: 'a>(
                 }
             });
 
-            trans_fn(tcx, module, ccx, caches, inst);
+            trans_fn(
+                tcx,
+                module,
+                ccx,
+                caches,
+                debug_context,
+                pending_functions,
+                global_asm,
+                inst,
+                linkage,
+            );
         }
         MonoItem::Static(def_id) => {
-            crate::constant::codegen_static(ccx, def_id);
+            crate::constant::codegen_static(tcx, module, ccx, def_id, linkage);
+        }
+        MonoItem::GlobalAsm(node_id) => {
+            bug!("global asm {:?} should be handled by codegen_mono_items", node_id)
         }
-        MonoItem::GlobalAsm(node_id) => tcx
-            .sess
-            .fatal(&format!("Unimplemented global asm mono item {:?}", node_id)),
     }
 }
 
@@ -60,7 +75,11 @@ fn trans_fn<'a, 'tcx: 'a>(
     module: &mut Module<impl Backend>,
     constants: &mut crate::constant::ConstantCx,
     caches: &mut Caches<'tcx>,
+    debug_context: Option<&mut DebugContext<'tcx>>,
+    pending_functions: Option<&mut Vec<PendingFunction>>,
+    global_asm: &mut String,
     instance: Instance<'tcx>,
+    linkage: Linkage,
 ) {
     // Step 1. Get mir
     let mir = tcx.instance_mir(instance.def);
@@ -68,11 +87,18 @@ fn trans_fn<'a, 'tcx: 'a>(
     // Step 2. Declare function
     let (name, sig) = get_function_name_and_sig(tcx, instance);
     let func_id = module
-        .declare_function(&name, Linkage::Export, &sig)
+        .declare_function(&name, linkage, &sig)
         .unwrap();
 
+    let debug_context = debug_context
+        .map(|debug_context| FunctionDebugContext::new(tcx, debug_context, mir, &name, &sig));
+
     // Step 3. Make FunctionBuilder
     let mut func = Function::with_name_signature(ExternalName::user(0, 0), sig);
+    if debug_context.is_some() {
+        // Track which locals are stored in which values for the variable location lists
+        func.dfg.collect_debug_info();
+    }
     let mut func_ctx = FunctionBuilderContext::new();
     let mut bcx = FunctionBuilder::new(&mut func, &mut func_ctx);
 
@@ -100,8 +126,11 @@ fn trans_fn<'a, 'tcx: 'a>(
         comments: HashMap::new(),
         constants,
         caches,
+        global_asm,
+        inline_asm_index: 0,
 
         top_nop: None,
+        source_info_set: indexmap::IndexSet::new(),
     };
 
     // Step 6. Codegen function
@@ -111,12 +140,19 @@ fn trans_fn<'a, 'tcx: 'a>(
     });
 
     // Step 7. Write function to file for debugging
+    let source_info_set = fx.source_info_set;
+    let local_map = fx.local_map;
     let mut writer = crate::pretty_clif::CommentWriter(fx.comments);
 
     let mut clif = String::new();
     if cfg!(debug_assertions) {
-        ::cranelift::codegen::write::decorate_function(&mut writer, &mut clif, &func, None)
-            .unwrap();
+        ::cranelift::codegen::write::decorate_function(
+            &mut writer,
+            &mut clif,
+            &func,
+            &Default::default(),
+        )
+        .unwrap();
         let clif_file_name = format!(
             "{}/{}__{}.clif",
             concat!(env!("CARGO_MANIFEST_DIR"), "/target/out/clif"),
@@ -131,7 +167,24 @@ fn trans_fn<'a, 'tcx: 'a>(
     // Step 8. Verify function
     verify_func(tcx, writer, &func);
 
-    // Step 9. Define function
+    // Step 9. Add the parts of the debuginfo which need the `TyCtxt`
+    let debug_info = debug_context.map(|debug_context| {
+        debug_context.finish(tcx, &local_map, &source_info_set)
+    });
+
+    // Step 10. Define function
+    // Compiling doesn't need the `TyCtxt`, so it is left to the workers of `crate::emit_pool`,
+    // which complete the debuginfo afterwards. Only the JIT compiles right away.
+    if let Some(pending_functions) = pending_functions {
+        pending_functions.push(PendingFunction {
+            name,
+            func_id,
+            func,
+            debug_info,
+        });
+        return;
+    }
+    assert!(debug_info.is_none(), "debuginfo is not supported in JIT mode");
     caches.context.func = func;
     module
         .define_function(func_id, &mut caches.context)
@@ -169,6 +222,7 @@ fn codegen_fn_content<'a, 'tcx: 'a>(fx: &mut FunctionCx<'a, 'tcx, impl Backend>)
 
         fx.bcx.ins().nop();
         for stmt in &bb_data.statements {
+            fx.set_debug_loc(stmt.source_info);
             trans_stmt(fx, ebb, stmt);
         }
 
@@ -181,6 +235,8 @@ fn codegen_fn_content<'a, 'tcx: 'a>(fx: &mut FunctionCx<'a, 'tcx, impl Backend>)
         let inst = fx.bcx.func.layout.last_inst(ebb).unwrap();
         fx.add_comment(inst, terminator_head);
 
+        fx.set_debug_loc(bb_data.terminator().source_info);
+
         match &bb_data.terminator().kind {
             TerminatorKind::Goto { target } => {
                 let ebb = fx.get_ebb(*target);
@@ -215,13 +271,23 @@ fn codegen_fn_content<'a, 'tcx: 'a>(fx: &mut FunctionCx<'a, 'tcx, impl Backend>)
                 targets,
             } => {
                 let discr = trans_operand(fx, discr).load_value(fx);
-                let mut switch = ::cranelift::frontend::Switch::new();
-                for (i, value) in values.iter().enumerate() {
-                    let ebb = fx.get_ebb(targets[i]);
-                    switch.set_entry(*value as u64, ebb);
-                }
                 let otherwise_ebb = fx.get_ebb(targets[targets.len() - 1]);
-                switch.emit(&mut fx.bcx, discr, otherwise_ebb);
+                if fx.bcx.func.dfg.value_type(discr) == types::I128 {
+                    // Switch only supports 64bit values
+                    for (i, value) in values.iter().enumerate() {
+                        let ebb = fx.get_ebb(targets[i]);
+                        let is_eq = codegen_icmp_imm(fx, IntCC::Equal, discr, *value as i128);
+                        fx.bcx.ins().brnz(is_eq, ebb, &[]);
+                    }
+                    fx.bcx.ins().jump(otherwise_ebb, &[]);
+                } else {
+                    let mut switch = ::cranelift::frontend::Switch::new();
+                    for (i, value) in values.iter().enumerate() {
+                        let ebb = fx.get_ebb(targets[i]);
+                        switch.set_entry(*value as u64, ebb);
+                    }
+                    switch.emit(&mut fx.bcx, discr, otherwise_ebb);
+                }
             }
             TerminatorKind::Call {
                 func,
@@ -236,6 +302,7 @@ fn codegen_fn_content<'a, 'tcx: 'a>(fx: &mut FunctionCx<'a, 'tcx, impl Backend>)
                 trap_unreachable(&mut fx.bcx);
             }
             TerminatorKind::Yield { .. }
+            | TerminatorKind::GeneratorDrop
             | TerminatorKind::FalseEdges { .. }
             | TerminatorKind::FalseUnwind { .. }
             | TerminatorKind::DropAndReplace { .. } => {
@@ -267,7 +334,20 @@ fn codegen_fn_content<'a, 'tcx: 'a>(fx: &mut FunctionCx<'a, 'tcx, impl Backend>)
                     drop_place.write_place_ref(fx, arg_place);
                     match ty.sty {
                         ty::Dynamic(..) => {
-                            unimpl!("Drop for trait object");
+                            let (ptr, vtable) = arg_place.to_cvalue(fx).load_value_pair(fx);
+                            let drop_fn = crate::vtable::drop_fn_of_obj(fx, vtable);
+
+                            // A null drop fn means that the type doesn't need to be dropped
+                            let target_ebb = fx.get_ebb(*target);
+                            fx.bcx.ins().brz(drop_fn, target_ebb, &[]);
+
+                            // `drop_in_place` for the concrete type takes a thin pointer
+                            let sig = fx.bcx.import_signature(Signature {
+                                call_conv: CallConv::Fast,
+                                params: vec![AbiParam::new(fx.pointer_type)],
+                                returns: vec![],
+                            });
+                            fx.bcx.ins().call_indirect(sig, drop_fn, &[ptr]);
                         }
                         _ => {
                             let drop_fn_ty = drop_fn.ty(fx.tcx);
@@ -281,45 +361,11 @@ fn codegen_fn_content<'a, 'tcx: 'a>(fx: &mut FunctionCx<'a, 'tcx, impl Backend>)
                             );
                         }
                     }
-                    /*
-                    let (args1, args2);
-                    /*let mut args = if let Some(llextra) = place.llextra {
-                        args2 = [place.llval, llextra];
-                        &args2[..]
-                    } else {
-                        args1 = [place.llval];
-                        &args1[..]
-                    };*/
-                    let (drop_fn, fn_ty) = match ty.sty {
-                    ty::Dynamic(..) => {
-                    let fn_ty = drop_fn.ty(bx.cx.tcx);
-                    let sig = common::ty_fn_sig(bx.cx, fn_ty);
-                    let sig = bx.tcx().normalize_erasing_late_bound_regions(
-                    ty::ParamEnv::reveal_all(),
-                    &sig,
-                    );
-                    let fn_ty = FnType::new_vtable(bx.cx, sig, &[]);
-                    let vtable = args[1];
-                    args = &args[..1];
-                    (meth::DESTRUCTOR.get_fn(&bx, vtable, &fn_ty), fn_ty)
-                    }
-                    _ => {
-                    let value = place.to_cvalue(fx);
-                    (callee::get_fn(bx.cx, drop_fn),
-                    FnType::of_instance(bx.cx, &drop_fn))
-                    }
-                    };
-                    do_call(self, bx, fn_ty, drop_fn, args,
-                    Some((ReturnDest::Nothing, target)),
-                    unwind);*/
                 }
 
                 let target_ebb = fx.get_ebb(*target);
                 fx.bcx.ins().jump(target_ebb, &[]);
             }
-            TerminatorKind::GeneratorDrop => {
-                unimplemented!("terminator GeneratorDrop");
-            }
         };
     }
 
@@ -348,47 +394,7 @@ fn trans_stmt<'a, 'tcx: 'a>(
             variant_index,
         } => {
             let place = trans_place(fx, place);
-            let layout = place.layout();
-            if layout.for_variant(&*fx, *variant_index).abi == layout::Abi::Uninhabited {
-                return;
-            }
-            match layout.variants {
-                layout::Variants::Single { index } => {
-                    assert_eq!(index, *variant_index);
-                }
-                layout::Variants::Tagged { .. } => {
-                    let ptr = place.place_field(fx, mir::Field::new(0));
-                    let to = layout
-                        .ty
-                        .ty_adt_def()
-                        .unwrap()
-                        .discriminant_for_variant(fx.tcx, *variant_index)
-                        .val;
-                    let discr = CValue::const_val(fx, ptr.layout().ty, to as u64 as i64);
-                    ptr.write_cvalue(fx, discr);
-                }
-                layout::Variants::NicheFilling {
-                    dataful_variant,
-                    ref niche_variants,
-                    niche_start,
-                    ..
-                } => {
-                    if *variant_index != dataful_variant {
-                        let niche = place.place_field(fx, mir::Field::new(0));
-                        //let niche_llty = niche.layout.immediate_llvm_type(bx.cx);
-                        let niche_value =
-                            ((variant_index.as_u32() - niche_variants.start().as_u32()) as u128)
-                                .wrapping_add(niche_start);
-                        // FIXME(eddyb) Check the actual primitive type here.
-                        let niche_llval = if niche_value == 0 {
-                            CValue::const_val(fx, niche.layout().ty, 0)
-                        } else {
-                            CValue::const_val(fx, niche.layout().ty, niche_value as u64 as i64)
-                        };
-                        niche.write_cvalue(fx, niche_llval);
-                    }
-                }
-            }
+            trans_set_discriminant(fx, place, *variant_index);
         }
         StatementKind::Assign(to_place, rval) => {
             let lval = trans_place(fx, to_place);
@@ -457,7 +463,7 @@ fn trans_stmt<'a, 'tcx: 'a>(
                         UnOp::Neg => match layout.ty.sty {
                             ty::Int(_) => {
                                 let clif_ty = fx.clif_type(layout.ty).unwrap();
-                                let zero = fx.bcx.ins().iconst(clif_ty, 0);
+                                let zero = clif_iconst(fx, clif_ty, 0);
                                 fx.bcx.ins().isub(zero, val)
                             }
                             ty::Float(_) => fx.bcx.ins().fneg(val),
@@ -535,40 +541,30 @@ fn trans_stmt<'a, 'tcx: 'a>(
                             };
                             lval.write_cvalue(fx, CValue::ByVal(res, dest_layout));
                         }
-                        (ty::Int(_), ty::Float(_)) => {
-                            let from_ty = fx.clif_type(from_ty).unwrap();
-                            let from = operand.load_value(fx);
-                            // FIXME missing encoding for fcvt_from_sint.f32.i8
-                            let from = if from_ty == types::I8 || from_ty == types::I16 {
-                                fx.bcx.ins().sextend(types::I32, from)
-                            } else {
-                                from
+                        (ty::Int(_), ty::Float(_)) | (ty::Uint(_), ty::Float(_)) => {
+                            let from_signed = match from_ty.sty {
+                                ty::Int(_) => true,
+                                _ => false,
                             };
+                            let from = operand.load_value(fx);
                             let f_type = fx.clif_type(to_ty).unwrap();
-                            let res = fx.bcx.ins().fcvt_from_sint(f_type, from);
+                            let res = clif_int_to_float_cast(fx, from, from_signed, f_type);
                             lval.write_cvalue(fx, CValue::ByVal(res, dest_layout));
                         }
-                        (ty::Uint(_), ty::Float(_)) => {
-                            let from_ty = fx.clif_type(from_ty).unwrap();
-                            let from = operand.load_value(fx);
-                            // FIXME missing encoding for fcvt_from_uint.f32.i8
-                            let from = if from_ty == types::I8 || from_ty == types::I16 {
-                                fx.bcx.ins().uextend(types::I32, from)
-                            } else {
-                                from
+                        (ty::Float(_), ty::Int(_)) | (ty::Float(_), ty::Uint(_)) => {
+                            let to_signed = match to_ty.sty {
+                                ty::Int(_) => true,
+                                _ => false,
                             };
-                            let f_type = fx.clif_type(to_ty).unwrap();
-                            let res = fx.bcx.ins().fcvt_from_uint(f_type, from);
+                            let from = operand.load_value(fx);
+                            let i_type = fx.clif_type(to_ty).unwrap();
+                            let res = clif_float_to_int_cast(fx, from, i_type, to_signed);
                             lval.write_cvalue(fx, CValue::ByVal(res, dest_layout));
                         }
                         (ty::Bool, ty::Uint(_)) | (ty::Bool, ty::Int(_)) => {
                             let to_ty = fx.clif_type(to_ty).unwrap();
                             let from = operand.load_value(fx);
-                            let res = if to_ty != types::I8 {
-                                fx.bcx.ins().uextend(to_ty, from)
-                            } else {
-                                from
-                            };
+                            let res = clif_intcast(fx, from, to_ty, false);
                             lval.write_cvalue(fx, CValue::ByVal(res, dest_layout));
                         }
                         (ty::Adt(adt_def, _substs), ty::Uint(_)) | (ty::Adt(adt_def, _substs), ty::Int(_)) if adt_def.is_enum() => {
@@ -578,8 +574,22 @@ fn trans_stmt<'a, 'tcx: 'a>(
                         _ => unimpl!("rval misc {:?} {:?}", from_ty, to_ty),
                     }
                 }
-                Rvalue::Cast(CastKind::ClosureFnPointer, operand, ty) => {
-                    unimplemented!("rval closure_fn_ptr {:?} {:?}", operand, ty)
+                Rvalue::Cast(CastKind::ClosureFnPointer, operand, _ty) => {
+                    let operand = trans_operand(fx, operand);
+                    match operand.layout().ty.sty {
+                        ty::Closure(def_id, substs) => {
+                            let instance = crate::rustc_mir::monomorphize::resolve_closure(
+                                fx.tcx,
+                                def_id,
+                                substs,
+                                ty::ClosureKind::FnOnce,
+                            );
+                            let func_ref = fx.get_function_ref(instance);
+                            let func_addr = fx.bcx.ins().func_addr(fx.pointer_type, func_ref);
+                            lval.write_cvalue(fx, CValue::ByVal(func_addr, lval.layout()));
+                        }
+                        _ => bug!("{} cannot be cast to a fn ptr", operand.layout().ty),
+                    }
                 }
                 Rvalue::Cast(CastKind::Unsize, operand, _ty) => {
                     let operand = trans_operand(fx, operand);
@@ -637,7 +647,7 @@ fn trans_stmt<'a, 'tcx: 'a>(
                         .ty
                         .is_sized(fx.tcx.at(DUMMY_SP), ParamEnv::reveal_all()));
                     let ty_size = fx.layout_of(ty).size.bytes();
-                    let val = CValue::const_val(fx, fx.tcx.types.usize, ty_size as i64);
+                    let val = CValue::const_val(fx, fx.tcx.types.usize, ty_size.into());
                     lval.write_cvalue(fx, val);
                 }
                 Rvalue::Aggregate(kind, operands) => match **kind {
@@ -649,7 +659,37 @@ fn trans_stmt<'a, 'tcx: 'a>(
                             to.write_cvalue(fx, operand);
                         }
                     }
-                    _ => unimpl!("shouldn't exist at trans {:?}", rval),
+                    AggregateKind::Adt(adt_def, variant_index, _substs, _, active_field_index) => {
+                        let variant_dest = if adt_def.is_enum() {
+                            trans_set_discriminant(fx, lval, variant_index);
+                            lval.downcast_variant(fx, variant_index)
+                        } else {
+                            lval
+                        };
+                        for (i, operand) in operands.into_iter().enumerate() {
+                            let operand = trans_operand(fx, operand);
+                            // For unions only the active field is given
+                            let field_index = active_field_index.unwrap_or(i);
+                            let to = variant_dest.place_field(fx, mir::Field::new(field_index));
+                            to.write_cvalue(fx, operand);
+                        }
+                    }
+                    AggregateKind::Tuple | AggregateKind::Closure(..) => {
+                        for (i, operand) in operands.into_iter().enumerate() {
+                            let operand = trans_operand(fx, operand);
+                            let to = lval.place_field(fx, mir::Field::new(i));
+                            to.write_cvalue(fx, operand);
+                        }
+                    }
+                    AggregateKind::Generator(..) => {
+                        // Generators always start in the unresumed state
+                        trans_set_discriminant(fx, lval, VariantIdx::new(0));
+                        for (i, operand) in operands.into_iter().enumerate() {
+                            let operand = trans_operand(fx, operand);
+                            let to = lval.place_field(fx, mir::Field::new(i));
+                            to.write_cvalue(fx, operand);
+                        }
+                    }
                 },
             }
         }
@@ -661,7 +701,56 @@ fn trans_stmt<'a, 'tcx: 'a>(
         | StatementKind::AscribeUserType(..)
         | StatementKind::EscapeToRaw(..) => {}
 
-        StatementKind::InlineAsm { .. } => unimpl!("Inline assembly is not supported"),
+        StatementKind::InlineAsm {
+            asm,
+            outputs,
+            inputs,
+        } => crate::inline_asm::codegen_inline_asm(fx, asm, outputs, inputs),
+    }
+}
+
+fn trans_set_discriminant<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    place: CPlace<'tcx>,
+    variant_index: VariantIdx,
+) {
+    let layout = place.layout();
+    if layout.for_variant(&*fx, variant_index).abi == layout::Abi::Uninhabited {
+        return;
+    }
+    match layout.variants {
+        layout::Variants::Single { index } => {
+            assert_eq!(index, variant_index);
+        }
+        layout::Variants::Tagged { .. } => {
+            let ptr = place.place_field(fx, mir::Field::new(0));
+            // Generators use the variant index as discriminant
+            let to = layout
+                .ty
+                .ty_adt_def()
+                .map_or(variant_index.as_u32() as u128, |def| {
+                    def.discriminant_for_variant(fx.tcx, variant_index).val
+                });
+            let discr = CValue::const_val(fx, ptr.layout().ty, to);
+            ptr.write_cvalue(fx, discr);
+        }
+        layout::Variants::NicheFilling {
+            dataful_variant,
+            ref niche_variants,
+            niche_start,
+            ..
+        } => {
+            if variant_index != dataful_variant {
+                let niche = place.place_field(fx, mir::Field::new(0));
+                //let niche_llty = niche.layout.immediate_llvm_type(bx.cx);
+                let niche_value =
+                    ((variant_index.as_u32() - niche_variants.start().as_u32()) as u128)
+                        .wrapping_add(niche_start);
+                // FIXME(eddyb) Check the actual primitive type here.
+                let niche_llval = CValue::const_val(fx, niche.layout().ty, niche_value);
+                niche.write_cvalue(fx, niche_llval);
+            }
+        }
     }
 }
 
@@ -700,7 +789,7 @@ pub fn trans_get_discriminant<'a, 'tcx: 'a>(
                 .map_or(index.as_u32() as u128, |def| {
                     def.discriminant_for_variant(fx.tcx, index).val
                 });
-            return CValue::const_val(fx, dest_layout.ty, discr_val as u64 as i64);
+            return CValue::const_val(fx, dest_layout.ty, discr_val);
         }
         layout::Variants::Tagged { .. } | layout::Variants::NicheFilling { .. } => {}
     }
@@ -727,10 +816,7 @@ pub fn trans_get_discriminant<'a, 'tcx: 'a>(
             let niche_llty = fx.clif_type(discr_ty).unwrap();
             let dest_clif_ty = fx.clif_type(dest_layout.ty).unwrap();
             if niche_variants.start() == niche_variants.end() {
-                let b = fx
-                    .bcx
-                    .ins()
-                    .icmp_imm(IntCC::Equal, lldiscr, niche_start as u64 as i64);
+                let b = codegen_icmp_imm(fx, IntCC::Equal, lldiscr, niche_start as i128);
                 let if_true = fx
                     .bcx
                     .ins()
@@ -744,12 +830,13 @@ pub fn trans_get_discriminant<'a, 'tcx: 'a>(
             } else {
                 // Rebase from niche values to discriminant values.
                 let delta = niche_start.wrapping_sub(niche_variants.start().as_u32() as u128);
-                let delta = fx.bcx.ins().iconst(niche_llty, delta as u64 as i64);
+                let delta = clif_iconst(fx, niche_llty, delta);
                 let lldiscr = fx.bcx.ins().isub(lldiscr, delta);
-                let b = fx.bcx.ins().icmp_imm(
+                let b = codegen_icmp_imm(
+                    fx,
                     IntCC::UnsignedLessThanOrEqual,
                     lldiscr,
-                    niche_variants.end().as_u32() as i64,
+                    niche_variants.end().as_u32() as i128,
                 );
                 let if_true =
                     clif_intcast(fx, lldiscr, fx.clif_type(dest_layout.ty).unwrap(), false);
@@ -772,7 +859,7 @@ macro_rules! binop_match {
         assert_eq!($fx.tcx.types.bool, $ret_ty);
         let ret_layout = $fx.layout_of($ret_ty);
 
-        let b = $fx.bcx.ins().icmp(IntCC::$cc, $lhs, $rhs);
+        let b = crate::common::codegen_icmp($fx, IntCC::$cc, $lhs, $rhs);
         CValue::ByVal($fx.bcx.ins().bint(types::I8, b), ret_layout)
     }};
     (@single $fx:expr, $bug_fmt:expr, $var:expr, $signed:expr, $lhs:expr, $rhs:expr, $ret_ty:expr, fcmp($cc:ident)) => {{
@@ -852,6 +939,11 @@ pub fn trans_int_binop<'a, 'tcx: 'a>(
             "int binop requires lhs and rhs of same type"
         );
     }
+
+    if let Some(res) = crate::codegen_i128::maybe_codegen(fx, bin_op, false, signed, lhs, rhs, out_ty) {
+        return res;
+    }
+
     binop_match! {
         fx, bin_op, signed, lhs, rhs, out_ty, "int/uint";
         Add (_) iadd;
@@ -899,20 +991,82 @@ pub fn trans_checked_int_binop<'a, 'tcx: 'a>(
         );
     }
 
+    if let Some(res) = crate::codegen_i128::maybe_codegen(fx, bin_op, true, signed, in_lhs, in_rhs, out_ty) {
+        return res;
+    }
+
     let lhs = in_lhs.load_value(fx);
     let rhs = in_rhs.load_value(fx);
-    let res = match bin_op {
-        BinOp::Add => fx.bcx.ins().iadd(lhs, rhs),
-        BinOp::Sub => fx.bcx.ins().isub(lhs, rhs),
-        BinOp::Mul => fx.bcx.ins().imul(lhs, rhs),
-        BinOp::Shl => fx.bcx.ins().ishl(lhs, rhs),
-        BinOp::Shr => {
-            if !signed {
-                fx.bcx.ins().ushr(lhs, rhs)
+    let (res, has_overflow) = match bin_op {
+        BinOp::Add => {
+            let val = fx.bcx.ins().iadd(lhs, rhs);
+            let has_overflow = if !signed {
+                codegen_icmp(fx, IntCC::UnsignedLessThan, val, lhs)
+            } else {
+                let rhs_is_negative = codegen_icmp_imm(fx, IntCC::SignedLessThan, rhs, 0);
+                let slt = codegen_icmp(fx, IntCC::SignedLessThan, val, lhs);
+                fx.bcx.ins().bxor(rhs_is_negative, slt)
+            };
+            (val, has_overflow)
+        }
+        BinOp::Sub => {
+            let val = fx.bcx.ins().isub(lhs, rhs);
+            let has_overflow = if !signed {
+                codegen_icmp(fx, IntCC::UnsignedGreaterThan, val, lhs)
             } else {
-                fx.bcx.ins().sshr(lhs, rhs)
+                let rhs_is_negative = codegen_icmp_imm(fx, IntCC::SignedLessThan, rhs, 0);
+                let sgt = codegen_icmp(fx, IntCC::SignedGreaterThan, val, lhs);
+                fx.bcx.ins().bxor(rhs_is_negative, sgt)
+            };
+            (val, has_overflow)
+        }
+        BinOp::Mul => {
+            let ty = fx.bcx.func.dfg.value_type(lhs);
+            if ty == types::I64 {
+                let val = fx.bcx.ins().imul(lhs, rhs);
+                let has_overflow = if !signed {
+                    let val_hi = fx.bcx.ins().umulhi(lhs, rhs);
+                    fx.bcx.ins().icmp_imm(IntCC::NotEqual, val_hi, 0)
+                } else {
+                    // The high half must be the sign extension of the low half
+                    let val_hi = fx.bcx.ins().smulhi(lhs, rhs);
+                    let val_sign = fx.bcx.ins().sshr_imm(val, 63);
+                    fx.bcx.ins().icmp(IntCC::NotEqual, val_hi, val_sign)
+                };
+                (val, has_overflow)
+            } else {
+                // Multiply in the next wider type and check if the result fits
+                let wide_ty = ty.double_width().unwrap();
+                let wide_lhs = clif_intcast(fx, lhs, wide_ty, signed);
+                let wide_rhs = clif_intcast(fx, rhs, wide_ty, signed);
+                let wide_val = fx.bcx.ins().imul(wide_lhs, wide_rhs);
+                let val = fx.bcx.ins().ireduce(ty, wide_val);
+                let roundtrip = clif_intcast(fx, val, wide_ty, signed);
+                let has_overflow = fx.bcx.ins().icmp(IntCC::NotEqual, wide_val, roundtrip);
+                (val, has_overflow)
             }
         }
+        BinOp::Shl | BinOp::Shr => {
+            // Shifting by the bit width of the type or more overflows. The
+            // shift amount is masked, like the non checked shift does. The rhs
+            // may have a different type than the lhs, so it is cast to the
+            // width of the lhs before masking. The overflow check uses the
+            // original rhs, as truncating it could hide the overflow.
+            let lhs_ty = fx.bcx.func.dfg.value_type(lhs);
+            let bits = lhs_ty.bits() as i64;
+            let rhs_casted = clif_intcast(fx, rhs, lhs_ty, false);
+            let actual_shift = fx.bcx.ins().band_imm(rhs_casted, bits - 1);
+            let val = match (bin_op, signed) {
+                (BinOp::Shl, _) => fx.bcx.ins().ishl(lhs, actual_shift),
+                (_, false) => fx.bcx.ins().ushr(lhs, actual_shift),
+                (_, true) => fx.bcx.ins().sshr(lhs, actual_shift),
+            };
+            let has_overflow = fx
+                .bcx
+                .ins()
+                .icmp_imm(IntCC::UnsignedGreaterThan, rhs, bits - 1);
+            (val, has_overflow)
+        }
         _ => bug!(
             "binop {:?} on checked int/uint lhs: {:?} rhs: {:?}",
             bin_op,
@@ -921,8 +1075,7 @@ pub fn trans_checked_int_binop<'a, 'tcx: 'a>(
         ),
     };
 
-    // TODO: check for overflow
-    let has_overflow = fx.bcx.ins().iconst(types::I8, 0);
+    let has_overflow = fx.bcx.ins().bint(types::I8, has_overflow);
 
     let out_place = CPlace::temp(fx, out_ty);
     let out_layout = out_place.layout();
@@ -1088,12 +1241,29 @@ pub fn trans_place<'a, 'tcx: 'a>(
                     };
                     base.place_index(fx, index)
                 }
-                ProjectionElem::Subslice { from, to } => unimpl!(
-                    "projection subslice {:?} from {} to {}",
-                    projection.base,
-                    from,
-                    to
-                ),
+                ProjectionElem::Subslice { from, to } => {
+                    // These indices are generated by slice patterns.
+                    // slice[from:-to] in Python terms.
+                    let from_index = fx.bcx.ins().iconst(fx.pointer_type, from as i64);
+                    let ptr = base.place_index(fx, from_index).expect_addr();
+
+                    match base.layout().ty.sty {
+                        ty::Array(elem_ty, len) => {
+                            let len = crate::constant::force_eval_const(fx, len).unwrap_usize(fx.tcx);
+                            let subslice_ty = fx.tcx.mk_array(elem_ty, len - from as u64 - to as u64);
+                            CPlace::Addr(ptr, None, fx.layout_of(subslice_ty))
+                        }
+                        ty::Slice(_elem_ty) => {
+                            let len = match base {
+                                CPlace::Addr(_, Some(len), _) => len,
+                                _ => bug!("slice place without length {:?}", base),
+                            };
+                            let len = fx.bcx.ins().iadd_imm(len, -(from as i64 + to as i64));
+                            CPlace::Addr(ptr, Some(len), base.layout())
+                        }
+                        _ => bug!("subslice of non array or slice type {:?}", base.layout().ty),
+                    }
+                }
                 ProjectionElem::Downcast(_adt_def, variant) => base.downcast_variant(fx, variant),
             }
         }
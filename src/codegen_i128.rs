@@ -0,0 +1,242 @@
+//! Replaces 128-bit operators with inline code or lib calls where Cranelift
+//! doesn't support them. The lib calls are provided by libgcc / compiler-rt.
+
+use crate::prelude::*;
+
+pub fn maybe_codegen<'a, 'tcx>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    bin_op: BinOp,
+    checked: bool,
+    is_signed: bool,
+    lhs: CValue<'tcx>,
+    rhs: CValue<'tcx>,
+    out_ty: Ty<'tcx>,
+) -> Option<CValue<'tcx>> {
+    let is_128bit = |ty| ty == fx.tcx.types.u128 || ty == fx.tcx.types.i128;
+    let lhs_is_128bit = is_128bit(lhs.layout().ty);
+    let rhs_is_128bit = is_128bit(rhs.layout().ty);
+
+    if !lhs_is_128bit {
+        if rhs_is_128bit {
+            // Only shifts may have operands of different types.
+            return Some(codegen_shift_by_128bit(fx, bin_op, checked, is_signed, lhs, rhs, out_ty));
+        }
+        return None;
+    }
+
+    match bin_op {
+        BinOp::BitAnd | BinOp::BitOr | BinOp::BitXor => {
+            assert!(!checked);
+            None
+        }
+        // Legalized by Cranelift. The overflow check is done by `trans_checked_int_binop`.
+        BinOp::Add | BinOp::Sub => None,
+        BinOp::Offset => unreachable!("offset should only be used on pointers, not 128bit ints"),
+        BinOp::Mul => {
+            if checked {
+                let lhs_val = lhs.load_value(fx);
+                let rhs_val = rhs.load_value(fx);
+                let (val, has_overflow) = if is_signed {
+                    codegen_i128_mul_overflow(fx, lhs_val, rhs_val)
+                } else {
+                    codegen_u128_mul_overflow(fx, lhs_val, rhs_val)
+                };
+                let has_overflow = fx.bcx.ins().bint(types::I8, has_overflow);
+                Some(write_checked_result(fx, val, has_overflow, out_ty))
+            } else {
+                Some(fx.easy_call("__multi3", &[lhs, rhs], lhs.layout().ty))
+            }
+        }
+        BinOp::Div => {
+            assert!(!checked);
+            if is_signed {
+                Some(fx.easy_call("__divti3", &[lhs, rhs], fx.tcx.types.i128))
+            } else {
+                Some(fx.easy_call("__udivti3", &[lhs, rhs], fx.tcx.types.u128))
+            }
+        }
+        BinOp::Rem => {
+            assert!(!checked);
+            if is_signed {
+                Some(fx.easy_call("__modti3", &[lhs, rhs], fx.tcx.types.i128))
+            } else {
+                Some(fx.easy_call("__umodti3", &[lhs, rhs], fx.tcx.types.u128))
+            }
+        }
+        // Handled by `codegen_icmp`
+        BinOp::Lt | BinOp::Le | BinOp::Eq | BinOp::Ge | BinOp::Gt | BinOp::Ne => {
+            assert!(!checked);
+            None
+        }
+        BinOp::Shl | BinOp::Shr => {
+            let rhs_val = rhs.load_value(fx);
+            let has_overflow = if checked {
+                let has_overflow = codegen_icmp_imm(fx, IntCC::UnsignedGreaterThan, rhs_val, 127);
+                Some(fx.bcx.ins().bint(types::I8, has_overflow))
+            } else {
+                None
+            };
+
+            // The libgcc shift functions have undefined behaviour for shift amounts >= 128
+            let truncated_rhs = clif_intcast(fx, rhs_val, types::I32, false);
+            let truncated_rhs = fx.bcx.ins().band_imm(truncated_rhs, 127);
+            let truncated_rhs = CValue::ByVal(truncated_rhs, fx.layout_of(fx.tcx.types.u32));
+            let val = match (bin_op, is_signed) {
+                (BinOp::Shl, _) => fx.easy_call("__ashlti3", &[lhs, truncated_rhs], lhs.layout().ty),
+                (BinOp::Shr, false) => {
+                    fx.easy_call("__lshrti3", &[lhs, truncated_rhs], fx.tcx.types.u128)
+                }
+                (BinOp::Shr, true) => {
+                    fx.easy_call("__ashrti3", &[lhs, truncated_rhs], fx.tcx.types.i128)
+                }
+                (_, _) => unreachable!(),
+            };
+
+            if let Some(has_overflow) = has_overflow {
+                let val = val.load_value(fx);
+                Some(write_checked_result(fx, val, has_overflow, out_ty))
+            } else {
+                Some(val)
+            }
+        }
+    }
+}
+
+/// Shifts a value narrower than 128 bits by a 128-bit amount. Cranelift only
+/// legalizes shift amounts up to 64 bits.
+fn codegen_shift_by_128bit<'a, 'tcx>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    bin_op: BinOp,
+    checked: bool,
+    is_signed: bool,
+    lhs: CValue<'tcx>,
+    rhs: CValue<'tcx>,
+    out_ty: Ty<'tcx>,
+) -> CValue<'tcx> {
+    let lhs_val = lhs.load_value(fx);
+    let rhs_val = rhs.load_value(fx);
+    let lhs_ty = fx.bcx.func.dfg.value_type(lhs_val);
+    let bits = lhs_ty.bits() as i64;
+
+    let truncated_rhs = clif_intcast(fx, rhs_val, lhs_ty, false);
+    let truncated_rhs = fx.bcx.ins().band_imm(truncated_rhs, bits - 1);
+    let val = match (bin_op, is_signed) {
+        (BinOp::Shl, _) => fx.bcx.ins().ishl(lhs_val, truncated_rhs),
+        (BinOp::Shr, false) => fx.bcx.ins().ushr(lhs_val, truncated_rhs),
+        (BinOp::Shr, true) => fx.bcx.ins().sshr(lhs_val, truncated_rhs),
+        (_, _) => bug!("binop {:?} with 128bit rhs on {:?}", bin_op, lhs.layout().ty),
+    };
+
+    if checked {
+        let has_overflow = codegen_icmp_imm(fx, IntCC::UnsignedGreaterThan, rhs_val, (bits - 1) as i128);
+        let has_overflow = fx.bcx.ins().bint(types::I8, has_overflow);
+        write_checked_result(fx, val, has_overflow, out_ty)
+    } else {
+        CValue::ByVal(val, lhs.layout())
+    }
+}
+
+fn write_checked_result<'a, 'tcx>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    val: Value,
+    has_overflow: Value,
+    out_ty: Ty<'tcx>,
+) -> CValue<'tcx> {
+    let out_place = CPlace::temp(fx, out_ty);
+    let out_layout = out_place.layout();
+    out_place.write_cvalue(fx, CValue::ByValPair(val, has_overflow, out_layout));
+    out_place.to_cvalue(fx)
+}
+
+/// Multiplies two u128 by splitting them into 64-bit halves. libgcc doesn't provide a
+/// function returning the overflow flag.
+fn codegen_u128_mul_overflow<'a, 'tcx>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    lhs: Value,
+    rhs: Value,
+) -> (Value, Value) {
+    let (lhs_lsb, lhs_msb) = fx.bcx.ins().isplit(lhs);
+    let (rhs_lsb, rhs_msb) = fx.bcx.ins().isplit(rhs);
+
+    // lhs * rhs = lhs_lsb * rhs_lsb
+    //           + (lhs_msb * rhs_lsb + lhs_lsb * rhs_msb) << 64
+    //           + (lhs_msb * rhs_msb) << 128
+    let lsb = fx.bcx.ins().imul(lhs_lsb, rhs_lsb);
+    let lsb_carry = fx.bcx.ins().umulhi(lhs_lsb, rhs_lsb);
+    let cross_a = fx.bcx.ins().imul(lhs_msb, rhs_lsb);
+    let cross_b = fx.bcx.ins().imul(lhs_lsb, rhs_msb);
+    let msb_a = fx.bcx.ins().iadd(lsb_carry, cross_a);
+    let carry_a = fx.bcx.ins().icmp(IntCC::UnsignedLessThan, msb_a, lsb_carry);
+    let msb = fx.bcx.ins().iadd(msb_a, cross_b);
+    let carry_b = fx.bcx.ins().icmp(IntCC::UnsignedLessThan, msb, msb_a);
+    let val = fx.bcx.ins().iconcat(lsb, msb);
+
+    let lhs_msb_non_zero = fx.bcx.ins().icmp_imm(IntCC::NotEqual, lhs_msb, 0);
+    let rhs_msb_non_zero = fx.bcx.ins().icmp_imm(IntCC::NotEqual, rhs_msb, 0);
+    let both_msb_non_zero = fx.bcx.ins().band(lhs_msb_non_zero, rhs_msb_non_zero);
+    let cross_a_hi = fx.bcx.ins().umulhi(lhs_msb, rhs_lsb);
+    let cross_a_overflow = fx.bcx.ins().icmp_imm(IntCC::NotEqual, cross_a_hi, 0);
+    let cross_b_hi = fx.bcx.ins().umulhi(lhs_lsb, rhs_msb);
+    let cross_b_overflow = fx.bcx.ins().icmp_imm(IntCC::NotEqual, cross_b_hi, 0);
+    let carry = fx.bcx.ins().bor(carry_a, carry_b);
+    let has_overflow = fx.bcx.ins().bor(both_msb_non_zero, cross_a_overflow);
+    let has_overflow = fx.bcx.ins().bor(has_overflow, cross_b_overflow);
+    let has_overflow = fx.bcx.ins().bor(has_overflow, carry);
+
+    (val, has_overflow)
+}
+
+/// Multiplies the absolute values and checks that the result fits in the signed range.
+/// libgcc doesn't provide `__muloti4`.
+fn codegen_i128_mul_overflow<'a, 'tcx>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    lhs: Value,
+    rhs: Value,
+) -> (Value, Value) {
+    let (lhs_abs, lhs_sign) = codegen_i128_abs(fx, lhs);
+    let (rhs_abs, rhs_sign) = codegen_i128_abs(fx, rhs);
+    let (abs_val, abs_overflow) = codegen_u128_mul_overflow(fx, lhs_abs, rhs_abs);
+
+    // Negate the result when exactly one of the operands is negative
+    let sign = fx.bcx.ins().bxor(lhs_sign, rhs_sign);
+    let sign_mask = fx.bcx.ins().iconcat(sign, sign);
+    let val = fx.bcx.ins().bxor(abs_val, sign_mask);
+    let val = fx.bcx.ins().isub(val, sign_mask);
+
+    // A positive result must be < 2^127, a negative result must be <= 2^127
+    let (abs_lsb, abs_msb) = fx.bcx.ins().isplit(abs_val);
+    let is_negative = fx.bcx.ins().icmp_imm(IntCC::NotEqual, sign, 0);
+    let is_positive = fx.bcx.ins().icmp_imm(IntCC::Equal, sign, 0);
+    let pos_overflow = fx
+        .bcx
+        .ins()
+        .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, abs_msb, 1 << 63);
+    let msb_gt = fx
+        .bcx
+        .ins()
+        .icmp_imm(IntCC::UnsignedGreaterThan, abs_msb, 1 << 63);
+    let msb_eq = fx.bcx.ins().icmp_imm(IntCC::Equal, abs_msb, 1 << 63);
+    let lsb_non_zero = fx.bcx.ins().icmp_imm(IntCC::NotEqual, abs_lsb, 0);
+    let neg_overflow = fx.bcx.ins().band(msb_eq, lsb_non_zero);
+    let neg_overflow = fx.bcx.ins().bor(msb_gt, neg_overflow);
+    let neg_overflow = fx.bcx.ins().band(is_negative, neg_overflow);
+    let pos_overflow = fx.bcx.ins().band(is_positive, pos_overflow);
+    let range_overflow = fx.bcx.ins().bor(pos_overflow, neg_overflow);
+    let has_overflow = fx.bcx.ins().bor(abs_overflow, range_overflow);
+
+    (val, has_overflow)
+}
+
+/// Returns the absolute value and an i64 which is all ones when `val` is negative and zero
+/// otherwise.
+fn codegen_i128_abs<'a, 'tcx>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    val: Value,
+) -> (Value, Value) {
+    let (_lsb, msb) = fx.bcx.ins().isplit(val);
+    let sign = fx.bcx.ins().sshr_imm(msb, 63);
+    let sign_mask = fx.bcx.ins().iconcat(sign, sign);
+    let abs = fx.bcx.ins().bxor(val, sign_mask);
+    let abs = fx.bcx.ins().isub(abs, sign_mask);
+    (abs, sign)
+}
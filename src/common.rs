@@ -20,12 +20,18 @@ pub fn pointer_ty(tcx: TyCtxt) -> types::Type {
     }
 }
 
-fn scalar_to_clif_type(tcx: TyCtxt, scalar: &Scalar) -> Type {
+pub fn scalar_to_clif_type(tcx: TyCtxt, scalar: &Scalar) -> Type {
+    match scalar.value {
+        layout::Primitive::Float(FloatTy::F32) => return types::F32,
+        layout::Primitive::Float(FloatTy::F64) => return types::F64,
+        _ => {}
+    }
     match scalar.value.size(&tcx).bits() {
         8 => types::I8,
         16 => types::I16,
         32 => types::I32,
         64 => types::I64,
+        128 => types::I128,
         size => bug!("Unsupported scalar size {}", size),
     }
 }
@@ -41,7 +47,7 @@ pub fn clif_type_from_ty<'a, 'tcx: 'a>(
             UintTy::U16 => types::I16,
             UintTy::U32 => types::I32,
             UintTy::U64 => types::I64,
-            UintTy::U128 => unimpl!("u128"),
+            UintTy::U128 => types::I128,
             UintTy::Usize => pointer_ty(tcx),
         },
         ty::Int(size) => match size {
@@ -49,7 +55,7 @@ pub fn clif_type_from_ty<'a, 'tcx: 'a>(
             IntTy::I16 => types::I16,
             IntTy::I32 => types::I32,
             IntTy::I64 => types::I64,
-            IntTy::I128 => unimpl!("i128"),
+            IntTy::I128 => types::I128,
             IntTy::Isize => pointer_ty(tcx),
         },
         ty::Char => types::I32,
@@ -85,6 +91,95 @@ pub fn codegen_select(bcx: &mut FunctionBuilder, cond: Value, lhs: Value, rhs: V
     }
 }
 
+pub fn clif_iconst<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    ty: Type,
+    val: u128,
+) -> Value {
+    if ty == types::I128 {
+        // There is no iconst.i128
+        let lsb = fx.bcx.ins().iconst(types::I64, val as u64 as i64);
+        let msb = fx.bcx.ins().iconst(types::I64, (val >> 64) as u64 as i64);
+        fx.bcx.ins().iconcat(lsb, msb)
+    } else {
+        fx.bcx.ins().iconst(ty, val as u64 as i64)
+    }
+}
+
+pub fn codegen_icmp<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    intcc: IntCC,
+    lhs: Value,
+    rhs: Value,
+) -> Value {
+    let lhs_ty = fx.bcx.func.dfg.value_type(lhs);
+    let rhs_ty = fx.bcx.func.dfg.value_type(rhs);
+    assert_eq!(lhs_ty, rhs_ty);
+    if lhs_ty != types::I128 {
+        return fx.bcx.ins().icmp(intcc, lhs, rhs);
+    }
+
+    // FIXME icmp.i128 is not legalized by Cranelift, so compare both halves
+    let (lhs_lsb, lhs_msb) = fx.bcx.ins().isplit(lhs);
+    let (rhs_lsb, rhs_msb) = fx.bcx.ins().isplit(rhs);
+    match intcc {
+        IntCC::Equal => {
+            let lsb_eq = fx.bcx.ins().icmp(IntCC::Equal, lhs_lsb, rhs_lsb);
+            let msb_eq = fx.bcx.ins().icmp(IntCC::Equal, lhs_msb, rhs_msb);
+            fx.bcx.ins().band(lsb_eq, msb_eq)
+        }
+        IntCC::NotEqual => {
+            let lsb_ne = fx.bcx.ins().icmp(IntCC::NotEqual, lhs_lsb, rhs_lsb);
+            let msb_ne = fx.bcx.ins().icmp(IntCC::NotEqual, lhs_msb, rhs_msb);
+            fx.bcx.ins().bor(lsb_ne, msb_ne)
+        }
+        _ => {
+            // The msb decides, unless they are equal. Then the lsb decides.
+            let (msb_cc, lsb_cc) = match intcc {
+                IntCC::SignedLessThan => (IntCC::SignedLessThan, IntCC::UnsignedLessThan),
+                IntCC::SignedLessThanOrEqual => {
+                    (IntCC::SignedLessThan, IntCC::UnsignedLessThanOrEqual)
+                }
+                IntCC::SignedGreaterThan => (IntCC::SignedGreaterThan, IntCC::UnsignedGreaterThan),
+                IntCC::SignedGreaterThanOrEqual => {
+                    (IntCC::SignedGreaterThan, IntCC::UnsignedGreaterThanOrEqual)
+                }
+                IntCC::UnsignedLessThan => (IntCC::UnsignedLessThan, IntCC::UnsignedLessThan),
+                IntCC::UnsignedLessThanOrEqual => {
+                    (IntCC::UnsignedLessThan, IntCC::UnsignedLessThanOrEqual)
+                }
+                IntCC::UnsignedGreaterThan => {
+                    (IntCC::UnsignedGreaterThan, IntCC::UnsignedGreaterThan)
+                }
+                IntCC::UnsignedGreaterThanOrEqual => {
+                    (IntCC::UnsignedGreaterThan, IntCC::UnsignedGreaterThanOrEqual)
+                }
+                _ => bug!("icmp.i128 {:?}", intcc),
+            };
+            let msb_eq = fx.bcx.ins().icmp(IntCC::Equal, lhs_msb, rhs_msb);
+            let msb_cc = fx.bcx.ins().icmp(msb_cc, lhs_msb, rhs_msb);
+            let lsb_cc = fx.bcx.ins().icmp(lsb_cc, lhs_lsb, rhs_lsb);
+            let lsb_decides = fx.bcx.ins().band(msb_eq, lsb_cc);
+            fx.bcx.ins().bor(msb_cc, lsb_decides)
+        }
+    }
+}
+
+pub fn codegen_icmp_imm<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    intcc: IntCC,
+    lhs: Value,
+    rhs: i128,
+) -> Value {
+    let lhs_ty = fx.bcx.func.dfg.value_type(lhs);
+    if lhs_ty == types::I128 {
+        let rhs = clif_iconst(fx, types::I128, rhs as u128);
+        codegen_icmp(fx, intcc, lhs, rhs)
+    } else {
+        fx.bcx.ins().icmp_imm(intcc, lhs, rhs as i64)
+    }
+}
+
 fn codegen_field<'a, 'tcx: 'a>(
     fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
     base: Value,
@@ -290,14 +385,14 @@ impl<'tcx> CValue<'tcx> {
     pub fn const_val<'a>(
         fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
         ty: Ty<'tcx>,
-        const_val: i64,
+        const_val: u128,
     ) -> CValue<'tcx>
     where
         'tcx: 'a,
     {
         let clif_ty = fx.clif_type(ty).unwrap();
         let layout = fx.layout_of(ty);
-        CValue::ByVal(fx.bcx.ins().iconst(clif_ty, const_val), layout)
+        CValue::ByVal(clif_iconst(fx, clif_ty, const_val), layout)
     }
 
     pub fn unchecked_cast_to(self, layout: TyLayout<'tcx>) -> Self {
@@ -419,7 +514,7 @@ impl<'a, 'tcx: 'a> CPlace<'tcx> {
         match self {
             CPlace::Var(var, _) => {
                 let data = from.load_value(fx);
-                fx.bcx.def_var(mir_var(var), data)
+                fx.def_local_var(var, data)
             }
             CPlace::Addr(addr, None, dst_layout) => {
                 match from {
@@ -600,6 +695,20 @@ pub fn clif_intcast<'a, 'tcx: 'a>(
     if from == to {
         return val;
     }
+    if to == types::I128 {
+        // FIXME extending to i128 is not legalized by Cranelift
+        let lsb = clif_intcast(fx, val, types::I64, signed);
+        let msb = if signed {
+            fx.bcx.ins().sshr_imm(lsb, 63)
+        } else {
+            fx.bcx.ins().iconst(types::I64, 0)
+        };
+        return fx.bcx.ins().iconcat(lsb, msb);
+    }
+    if from == types::I128 {
+        let (lsb, _msb) = fx.bcx.ins().isplit(val);
+        return clif_intcast(fx, lsb, to, signed);
+    }
     if to.wider_or_equal(from) {
         if signed {
             fx.bcx.ins().sextend(to, val)
@@ -611,6 +720,143 @@ pub fn clif_intcast<'a, 'tcx: 'a>(
     }
 }
 
+pub fn clif_int_to_float_cast<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    from: Value,
+    from_signed: bool,
+    to_ty: Type,
+) -> Value {
+    let from_ty = fx.bcx.func.dfg.value_type(from);
+
+    if from_ty == types::I128 {
+        let name = format!(
+            "__float{sign}ti{flt}f",
+            sign = if from_signed { "" } else { "un" },
+            flt = match to_ty {
+                types::F32 => "s",
+                types::F64 => "d",
+                _ => unreachable!("{:?}", to_ty),
+            },
+        );
+        return fx
+            .lib_call(&name, vec![types::I128], Some(to_ty), &[from])
+            .unwrap();
+    }
+
+    // FIXME missing encoding for fcvt_from_*int.f*.i8 and fcvt_from_*int.f*.i16
+    let from = if from_ty == types::I8 || from_ty == types::I16 {
+        clif_intcast(fx, from, types::I32, from_signed)
+    } else {
+        from
+    };
+
+    if from_signed {
+        fx.bcx.ins().fcvt_from_sint(to_ty, from)
+    } else {
+        fx.bcx.ins().fcvt_from_uint(to_ty, from)
+    }
+}
+
+/// Casts a float to an int. NaN becomes zero and values outside the range of the int type are
+/// clamped to the minimum or maximum value.
+pub fn clif_float_to_int_cast<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    from: Value,
+    to_ty: Type,
+    to_signed: bool,
+) -> Value {
+    let from_ty = fx.bcx.func.dfg.value_type(from);
+
+    if to_ty == types::I128 {
+        let name = format!(
+            "__fix{sign}{flt}fti",
+            sign = if to_signed { "" } else { "uns" },
+            flt = match from_ty {
+                types::F32 => "s",
+                types::F64 => "d",
+                _ => unreachable!("{:?}", from_ty),
+            },
+        );
+        let val = fx
+            .lib_call(&name, vec![from_ty], Some(types::I128), &[from])
+            .unwrap();
+
+        // The libgcc functions don't saturate, so NaN and out of range values are handled here.
+        let (min, max, float_min, float_max) = if to_signed {
+            (
+                i128::min_value() as u128,
+                i128::max_value() as u128,
+                i128::min_value() as f64,
+                -(i128::min_value() as f64),
+            )
+        } else {
+            (0, u128::max_value(), 0.0, u128::max_value() as f64)
+        };
+        let (float_min, float_max) = match from_ty {
+            types::F32 => (
+                fx.bcx.ins().f32const(float_min as f32),
+                fx.bcx.ins().f32const(float_max as f32),
+            ),
+            types::F64 => (
+                fx.bcx.ins().f64const(float_min),
+                fx.bcx.ins().f64const(float_max),
+            ),
+            _ => unreachable!("{:?}", from_ty),
+        };
+        let is_nan = fx.bcx.ins().fcmp(FloatCC::Unordered, from, from);
+        let has_underflow = fx.bcx.ins().fcmp(FloatCC::LessThan, from, float_min);
+        let has_overflow = fx.bcx.ins().fcmp(FloatCC::GreaterThanOrEqual, from, float_max);
+
+        // Select on the 64-bit halves, as select.i128 is not legalized by Cranelift
+        let (lsb, msb) = fx.bcx.ins().isplit(val);
+        let mut select_const = |cond, imm: u128, lsb, msb| {
+            let imm_lsb = fx.bcx.ins().iconst(types::I64, imm as u64 as i64);
+            let imm_msb = fx.bcx.ins().iconst(types::I64, (imm >> 64) as u64 as i64);
+            (
+                fx.bcx.ins().select(cond, imm_lsb, lsb),
+                fx.bcx.ins().select(cond, imm_msb, msb),
+            )
+        };
+        let (lsb, msb) = select_const(has_underflow, min, lsb, msb);
+        let (lsb, msb) = select_const(has_overflow, max, lsb, msb);
+        let (lsb, msb) = select_const(is_nan, 0, lsb, msb);
+        return fx.bcx.ins().iconcat(lsb, msb);
+    }
+
+    if to_ty == types::I8 || to_ty == types::I16 {
+        // FIXME missing encoding for fcvt_to_*int_sat.i8 and fcvt_to_*int_sat.i16
+        let val = if to_signed {
+            fx.bcx.ins().fcvt_to_sint_sat(types::I32, from)
+        } else {
+            fx.bcx.ins().fcvt_to_uint_sat(types::I32, from)
+        };
+        let (min, max) = match (to_ty, to_signed) {
+            (types::I8, false) => (0, u8::max_value() as i64),
+            (types::I16, false) => (0, u16::max_value() as i64),
+            (types::I8, true) => (i8::min_value() as i64, i8::max_value() as i64),
+            (types::I16, true) => (i16::min_value() as i64, i16::max_value() as i64),
+            _ => unreachable!(),
+        };
+        let min_val = fx.bcx.ins().iconst(types::I32, min);
+        let max_val = fx.bcx.ins().iconst(types::I32, max);
+
+        let val = if to_signed {
+            let has_underflow = fx.bcx.ins().icmp_imm(IntCC::SignedLessThan, val, min);
+            let has_overflow = fx.bcx.ins().icmp_imm(IntCC::SignedGreaterThan, val, max);
+            let bottom_capped = fx.bcx.ins().select(has_underflow, min_val, val);
+            fx.bcx.ins().select(has_overflow, max_val, bottom_capped)
+        } else {
+            let has_overflow = fx.bcx.ins().icmp_imm(IntCC::UnsignedGreaterThan, val, max);
+            fx.bcx.ins().select(has_overflow, max_val, val)
+        };
+        fx.bcx.ins().ireduce(to_ty, val)
+    } else if to_signed {
+        fx.bcx.ins().fcvt_to_sint_sat(to_ty, from)
+    } else {
+        fx.bcx.ins().fcvt_to_uint_sat(to_ty, from)
+    }
+}
+
 pub struct FunctionCx<'a, 'tcx: 'a, B: Backend> {
     pub tcx: TyCtxt<'a, 'tcx, 'tcx>,
     pub module: &'a mut Module<B>,
@@ -626,9 +872,16 @@ pub struct FunctionCx<'a, 'tcx: 'a, B: Backend> {
     pub comments: HashMap<Inst, String>,
     pub constants: &'a mut crate::constant::ConstantCx,
     pub caches: &'a mut Caches<'tcx>,
+    /// `global_asm!` items and outlined inline asm of the crate
+    pub global_asm: &'a mut String,
+    /// Number of inline asm blocks outlined from this function so far
+    pub inline_asm_index: u32,
 
     /// add_global_comment inserts a comment here
     pub top_nop: Option<Inst>,
+
+    /// The `SourceLoc` of an instruction is the index of its `SourceInfo` in this set
+    pub source_info_set: indexmap::IndexSet<SourceInfo>,
 }
 
 impl<'a, 'tcx: 'a, B: Backend + 'a> fmt::Debug for FunctionCx<'a, 'tcx, B> {
@@ -642,7 +895,7 @@ impl<'a, 'tcx: 'a, B: Backend + 'a> fmt::Debug for FunctionCx<'a, 'tcx, B> {
             &mut writer,
             &mut clif,
             &self.bcx.func,
-            None,
+            &Default::default(),
         )
         .unwrap();
         writeln!(f, "\n{}", clif)
@@ -708,4 +961,17 @@ impl<'a, 'tcx: 'a, B: Backend + 'a> FunctionCx<'a, 'tcx, B> {
     pub fn get_local_place(&mut self, local: Local) -> CPlace<'tcx> {
         *self.local_map.get(&local).unwrap()
     }
+
+    /// Define the ssa variable of `local` and label the value for the debuginfo.
+    pub fn def_local_var(&mut self, local: Local, val: Value) {
+        self.bcx.def_var(mir_var(local), val);
+        self.bcx
+            .set_val_label(val, ValueLabel::from_u32(local.index() as u32));
+    }
+
+    /// Attach `source_info` to all instructions inserted from now on.
+    pub fn set_debug_loc(&mut self, source_info: SourceInfo) {
+        let (index, _) = self.source_info_set.insert_full(source_info);
+        self.bcx.set_srcloc(SourceLoc::new(index as u32));
+    }
 }
@@ -1,8 +1,13 @@
+use std::alloc::{self, Layout};
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt::Write;
 
+use rustc::hir::CodegenFnAttrFlags;
 use rustc::mir::interpret::{
     read_target_uint, AllocId, AllocType, Allocation, ConstValue, EvalResult, GlobalId, Scalar,
 };
+use rustc::mir::mono::Linkage as RLinkage;
 use rustc::ty::Const;
 use rustc_mir::interpret::{
     EvalContext, MPlaceTy, Machine, Memory, MemoryKind, OpTy, PlaceTy, Pointer, StackPopCleanup,
@@ -10,12 +15,17 @@ use rustc_mir::interpret::{
 
 use cranelift_module::*;
 
+use crate::object_patch::ObjectPatches;
 use crate::prelude::*;
 
 #[derive(Default)]
 pub struct ConstantCx {
     todo: HashSet<TodoItem>,
     done: HashSet<DataId>,
+    /// Thread locals for which an accessor has been added to the global asm
+    tls_accessors: HashSet<DefId>,
+    /// Symbols imported by `extern_weak` statics
+    weak_imports: HashSet<String>,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
@@ -29,15 +39,25 @@ impl ConstantCx {
         mut self,
         tcx: TyCtxt<'a, 'tcx, 'tcx>,
         module: &mut Module<B>,
+        object_patches: &mut ObjectPatches,
     ) {
         //println!("todo {:?}", self.todo);
-        define_all_allocs(tcx, module, &mut self);
+        define_all_allocs(tcx, module, &mut self, object_patches);
+        object_patches.weak_imports.extend(self.weak_imports.drain());
         //println!("done {:?}", self.done);
         self.done.clear();
     }
 }
 
-pub fn codegen_static<'a, 'tcx: 'a>(ccx: &mut ConstantCx, def_id: DefId) {
+pub fn codegen_static<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    module: &mut Module<impl Backend>,
+    ccx: &mut ConstantCx,
+    def_id: DefId,
+    linkage: Linkage,
+) {
+    // Declare with the right linkage now, `define_all_allocs` only looks the data id up again.
+    data_id_for_static(tcx, module, ccx, def_id, linkage);
     ccx.todo.insert(TodoItem::Static(def_id));
 }
 
@@ -45,7 +65,16 @@ pub fn codegen_static_ref<'a, 'tcx: 'a>(
     fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
     static_: &Static<'tcx>,
 ) -> CPlace<'tcx> {
-    let data_id = data_id_for_static(fx.tcx, fx.module, static_.def_id, Linkage::Import);
+    if is_thread_local(fx.tcx, static_.def_id) {
+        return cplace_for_thread_local(fx, static_.ty, static_.def_id);
+    }
+    let data_id = data_id_for_static(
+        fx.tcx,
+        fx.module,
+        fx.constants,
+        static_.def_id,
+        Linkage::Import,
+    );
     cplace_for_dataid(fx, static_.ty, data_id)
 }
 
@@ -101,15 +130,15 @@ fn trans_const_value<'a, 'tcx: 'a>(
     match ty.sty {
         ty::Bool => {
             let bits = const_.val.try_to_bits(layout.size).unwrap();
-            CValue::const_val(fx, ty, bits as u64 as i64)
+            CValue::const_val(fx, ty, bits)
         }
         ty::Uint(_) => {
             let bits = const_.val.try_to_bits(layout.size).unwrap();
-            CValue::const_val(fx, ty, bits as u64 as i64)
+            CValue::const_val(fx, ty, bits)
         }
         ty::Int(_) => {
             let bits = const_.val.try_to_bits(layout.size).unwrap();
-            CValue::const_val(fx, ty, bits as i128 as i64)
+            CValue::const_val(fx, ty, bits)
         }
         ty::FnDef(def_id, substs) => {
             let func_ref = fx.get_function_ref(
@@ -160,32 +189,131 @@ fn trans_const_place<'a, 'tcx: 'a>(
     //println!("const value: {:?} allocation: {:?}", value, alloc);
     let alloc_id = fx.tcx.alloc_map.lock().allocate(alloc);
     fx.constants.todo.insert(TodoItem::Alloc(alloc_id));
-    let data_id = data_id_for_alloc_id(fx.module, alloc_id);
+    let data_id = data_id_for_alloc_id(fx.tcx, fx.module, alloc_id);
     cplace_for_dataid(fx, const_.ty, data_id)
 }
 
-fn data_id_for_alloc_id<B: Backend>(module: &mut Module<B>, alloc_id: AllocId) -> DataId {
+fn data_id_for_alloc_id<'a, 'tcx: 'a, B: Backend>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    module: &mut Module<B>,
+    alloc_id: AllocId,
+) -> DataId {
+    let align = match tcx.alloc_map.lock().get(alloc_id) {
+        Some(AllocType::Memory(alloc)) => alloc.align,
+        alloc => bug!("{:?} is not a memory allocation: {:?}", alloc_id, alloc),
+    };
     module
-        .declare_data(&alloc_id.0.to_string(), Linkage::Local, false)
+        .declare_data(
+            &alloc_id.0.to_string(),
+            Linkage::Local,
+            false,
+            clif_data_align(align.bytes()),
+        )
         .unwrap()
 }
 
 fn data_id_for_static<'a, 'tcx: 'a, B: Backend>(
     tcx: TyCtxt<'a, 'tcx, 'tcx>,
     module: &mut Module<B>,
+    ccx: &mut ConstantCx,
     def_id: DefId,
     linkage: Linkage,
 ) -> DataId {
     let symbol_name = tcx.symbol_name(Instance::mono(tcx, def_id)).as_str();
-    let is_mutable = if let ::rustc::hir::Mutability::MutMutable = tcx.is_static(def_id).unwrap() {
+    if tcx.codegen_fn_attrs(def_id).linkage.is_some() && tcx.is_foreign_item(def_id) {
+        return data_id_for_extern_with_linkage(tcx, module, ccx, def_id, &*symbol_name);
+    }
+    let is_mutable = is_mutable_static(tcx, def_id);
+    let align = tcx
+        .layout_of(ParamEnv::reveal_all().and(tcx.type_of(def_id)))
+        .unwrap()
+        .align
+        .abi;
+    module
+        .declare_data(
+            &*symbol_name,
+            linkage,
+            is_mutable,
+            clif_data_align(align.bytes()),
+        )
+        .unwrap()
+}
+
+/// `static mut` and statics with interior mutability are written at runtime.
+fn is_mutable_static<'a, 'tcx: 'a>(tcx: TyCtxt<'a, 'tcx, 'tcx>, def_id: DefId) -> bool {
+    if let ::rustc::hir::Mutability::MutMutable = tcx.is_static(def_id).unwrap() {
         true
     } else {
         !tcx.type_of(def_id)
             .is_freeze(tcx, ParamEnv::reveal_all(), DUMMY_SP)
-    };
-    module
-        .declare_data(&*symbol_name, linkage, is_mutable)
-        .unwrap()
+    }
+}
+
+/// A foreign static with `#[linkage]` is a pointer to the symbol it names, which may be null for
+/// `extern_weak`. Like the LLVM backend, a local pointer sized object containing the address of
+/// the symbol is used as the static. Faerie can't declare weak imports, so `extern_weak` symbols
+/// are made weak by `crate::object_patch`.
+fn data_id_for_extern_with_linkage<'a, 'tcx: 'a, B: Backend>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    module: &mut Module<B>,
+    ccx: &mut ConstantCx,
+    def_id: DefId,
+    symbol_name: &str,
+) -> DataId {
+    let layout = tcx
+        .layout_of(ParamEnv::reveal_all().and(tcx.type_of(def_id)))
+        .unwrap();
+    if layout.size != tcx.data_layout.pointer_size {
+        tcx.sess.span_fatal(
+            tcx.def_span(def_id),
+            "must have type `*const T` or `*mut T` due to `#[linkage]` attribute",
+        );
+    }
+
+    let target_id = module
+        .declare_data(symbol_name, Linkage::Import, false, None)
+        .unwrap();
+    if tcx.codegen_fn_attrs(def_id).linkage == Some(RLinkage::ExternalWeak) {
+        ccx.weak_imports.insert(symbol_name.to_string());
+    }
+
+    let ref_name = format!("_rust_extern_with_linkage_{}", symbol_name);
+    let ref_id = module
+        .declare_data(
+            &ref_name,
+            Linkage::Local,
+            false,
+            clif_data_align(layout.align.abi.bytes()),
+        )
+        .unwrap();
+    let mut data_ctx = DataContext::new();
+    data_ctx.define_zeroinit(layout.size.bytes() as usize);
+    let target = module.declare_data_in_data(target_id, &mut data_ctx);
+    data_ctx.write_data_addr(0, target, 0);
+    match module.define_data(ref_id, &data_ctx) {
+        // Every use of the static declares the reference.
+        Ok(()) | Err(ModuleError::DuplicateDefinition(_)) => {}
+        Err(err) => bug!("{:?}", err),
+    }
+    ref_id
+}
+
+/// Cranelift supports alignments up to 128 bytes. Bigger alignments are applied by
+/// `crate::object_patch`.
+pub fn clif_data_align(align: u64) -> Option<u8> {
+    Some(align.min(128) as u8)
+}
+
+pub fn is_thread_local<'a, 'tcx: 'a>(tcx: TyCtxt<'a, 'tcx, 'tcx>, def_id: DefId) -> bool {
+    tcx.codegen_fn_attrs(def_id)
+        .flags
+        .contains(CodegenFnAttrFlags::THREAD_LOCAL)
+}
+
+/// The JIT can't assemble the TLS accessors. Instead thread locals are declared as normal data,
+/// which is used as initializer for the per thread copies returned by `jit_tls_get_addr`.
+fn emulate_tls() -> bool {
+    std::env::var("SHOULD_RUN").is_ok()
 }
 
 fn cplace_for_dataid<'a, 'tcx: 'a>(
@@ -200,20 +328,128 @@ fn cplace_for_dataid<'a, 'tcx: 'a>(
     CPlace::Addr(global_ptr, None, layout)
 }
 
+fn cplace_for_thread_local<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    ty: Ty<'tcx>,
+    def_id: DefId,
+) -> CPlace<'tcx> {
+    let layout = fx.layout_of(fx.monomorphize(&ty));
+    assert!(!layout.is_unsized(), "unsized statics aren't supported");
+    let pointer_type = fx.pointer_type;
+    let tls_ptr = if emulate_tls() {
+        let data_id = data_id_for_static(fx.tcx, fx.module, fx.constants, def_id, Linkage::Import);
+        let local_data_id = fx.module.declare_data_in_func(data_id, &mut fx.bcx.func);
+        let template = fx.bcx.ins().global_value(pointer_type, local_data_id);
+        let size = fx
+            .bcx
+            .ins()
+            .iconst(pointer_type, layout.size.bytes() as i64);
+        let align = fx
+            .bcx
+            .ins()
+            .iconst(pointer_type, layout.align.abi.bytes() as i64);
+        fx.lib_call(
+            "__cg_clif_jit_tls_get_addr",
+            vec![pointer_type, pointer_type, pointer_type],
+            Some(pointer_type),
+            &[template, size, align],
+        )
+        .unwrap()
+    } else {
+        let accessor = tls_accessor(fx, def_id);
+        fx.lib_call(&accessor, vec![], Some(pointer_type), &[])
+            .unwrap()
+    };
+    CPlace::Addr(tls_ptr, None, layout)
+}
+
+/// Cranelift can't emit TLS relocations, so thread locals are accessed through an accessor
+/// function, which is assembled by the system assembler. It uses the general dynamic model, so it
+/// works for every crate type. The linker relaxes it to a cheaper model where possible.
+fn tls_accessor<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    def_id: DefId,
+) -> String {
+    let symbol_name = fx.tcx.symbol_name(Instance::mono(fx.tcx, def_id)).as_str();
+    let accessor = format!("{}$tls_get_addr", symbol_name);
+    if !fx.constants.tls_accessors.insert(def_id) {
+        return accessor;
+    }
+
+    // The same accessor may be generated by multiple crates.
+    let asm = &mut fx.global_asm;
+    writeln!(asm, ".pushsection .text").unwrap();
+    writeln!(asm, ".p2align 4").unwrap();
+    writeln!(asm, ".weak \"{}\"", accessor).unwrap();
+    writeln!(asm, ".hidden \"{}\"", accessor).unwrap();
+    writeln!(asm, ".type \"{}\", @function", accessor).unwrap();
+    writeln!(asm, "\"{}\":", accessor).unwrap();
+    // Keep the stack aligned for `__tls_get_addr`
+    writeln!(asm, "    push %rbp").unwrap();
+    // The exact instruction sequence the linker expects for the general dynamic model
+    writeln!(asm, "    .byte 0x66").unwrap();
+    writeln!(asm, "    leaq \"{}\"@tlsgd(%rip), %rdi", symbol_name).unwrap();
+    writeln!(asm, "    .value 0x6666").unwrap();
+    writeln!(asm, "    rex64").unwrap();
+    writeln!(asm, "    call __tls_get_addr@PLT").unwrap();
+    writeln!(asm, "    pop %rbp").unwrap();
+    writeln!(asm, "    ret").unwrap();
+    writeln!(asm, ".popsection").unwrap();
+    writeln!(asm).unwrap();
+
+    accessor
+}
+
+/// The copies of the thread local statics of a thread, keyed by the address of their initializer.
+/// They are freed when the thread exits.
+struct JitThreadLocals(HashMap<usize, (*mut u8, Layout)>);
+
+impl Drop for JitThreadLocals {
+    fn drop(&mut self) {
+        for &(ptr, layout) in self.0.values() {
+            unsafe {
+                alloc::dealloc(ptr, layout);
+            }
+        }
+    }
+}
+
+/// Returns the address of the current thread's copy of the thread local static with the given
+/// initializer. The copy is made on first use.
+pub extern "C" fn jit_tls_get_addr(template: *const u8, size: usize, align: usize) -> *mut u8 {
+    thread_local! {
+        static TLS: RefCell<JitThreadLocals> = RefCell::new(JitThreadLocals(HashMap::new()));
+    }
+
+    TLS.with(|tls| {
+        tls.borrow_mut()
+            .0
+            .entry(template as usize)
+            .or_insert_with(|| unsafe {
+                let layout = Layout::from_size_align(size.max(1), align).unwrap();
+                let ptr = alloc::alloc(layout);
+                ::std::ptr::copy_nonoverlapping(template, ptr, size);
+                (ptr, layout)
+            })
+            .0
+    })
+}
+
 fn define_all_allocs<'a, 'tcx: 'a, B: Backend + 'a>(
     tcx: TyCtxt<'a, 'tcx, 'tcx>,
     module: &mut Module<B>,
     cx: &mut ConstantCx,
+    object_patches: &mut ObjectPatches,
 ) {
     let memory = Memory::<TransPlaceInterpreter>::new(tcx.at(DUMMY_SP));
 
     while let Some(todo_item) = pop_set(&mut cx.todo) {
-        let (data_id, alloc) = match todo_item {
+        let (data_id, alloc, name, link_section, is_writable) = match todo_item {
             TodoItem::Alloc(alloc_id) => {
                 //println!("alloc_id {}", alloc_id);
-                let data_id = data_id_for_alloc_id(module, alloc_id);
+                let data_id = data_id_for_alloc_id(tcx, module, alloc_id);
                 let alloc = memory.get(alloc_id).unwrap();
-                (data_id, alloc)
+                (data_id, alloc, alloc_id.0.to_string(), None, false)
             }
             TodoItem::Static(def_id) => {
                 //println!("static {:?}", def_id);
@@ -229,8 +465,23 @@ fn define_all_allocs<'a, 'tcx: 'a, B: Backend + 'a>(
                     _ => bug!("static const eval returned {:#?}", const_),
                 };
 
-                let data_id = data_id_for_static(tcx, module, def_id, Linkage::Export);
-                (data_id, alloc)
+                let data_id = data_id_for_static(tcx, module, cx, def_id, Linkage::Import);
+                let symbol_name = tcx.symbol_name(instance).as_str().to_string();
+                let thread_local = is_thread_local(tcx, def_id);
+                if thread_local {
+                    object_patches.thread_locals.insert(symbol_name.clone());
+                }
+                if tcx
+                    .codegen_fn_attrs(def_id)
+                    .flags
+                    .contains(CodegenFnAttrFlags::USED)
+                {
+                    object_patches.used.insert(symbol_name.clone());
+                }
+                let link_section = tcx.codegen_fn_attrs(def_id).link_section;
+                // Every thread writes to its own copy of a thread local, never to the template.
+                let is_writable = thread_local || is_mutable_static(tcx, def_id);
+                (data_id, alloc, symbol_name, link_section, is_writable)
             }
         };
 
@@ -241,10 +492,29 @@ fn define_all_allocs<'a, 'tcx: 'a, B: Backend + 'a>(
 
         let mut data_ctx = DataContext::new();
 
-        data_ctx.define(alloc.bytes.to_vec().into_boxed_slice());
+        if let Some(link_section) = link_section {
+            data_ctx.define(alloc.bytes.to_vec().into_boxed_slice());
+            object_patches
+                .link_sections
+                .insert(name.clone(), link_section.as_str().to_string());
+        } else if is_writable
+            && alloc.relocations.iter().next().is_none()
+            && alloc.bytes.iter().all(|&byte| byte == 0)
+        {
+            // Immutable zero data stays in a read only section. Faerie has no `.bss`, so it still
+            // keeps all the zeros in memory and writes them into the object (even for a 64 MiB
+            // static), until `crate::object_patch` moves them into a `.bss` section.
+            data_ctx.define_zeroinit(alloc.bytes.len());
+            object_patches.zero_initialized.insert(name.clone());
+        } else {
+            data_ctx.define(alloc.bytes.to_vec().into_boxed_slice());
+        }
+        if alloc.align.bytes() > 128 {
+            object_patches.alignments.insert(name, alloc.align.bytes());
+        }
 
         for &(offset, (_tag, reloc)) in alloc.relocations.iter() {
-            let reloc_offset = {
+            let addend = {
                 let endianness = tcx.data_layout.endian;
                 let offset = offset.bytes() as usize;
                 let ptr_size = tcx.data_layout.pointer_size;
@@ -252,28 +522,30 @@ fn define_all_allocs<'a, 'tcx: 'a, B: Backend + 'a>(
                 read_target_uint(endianness, bytes).unwrap()
             };
 
-            let data_id = match tcx.alloc_map.lock().get(reloc).unwrap() {
+            let reloc_target_alloc = tcx.alloc_map.lock().get(reloc).unwrap();
+            let data_id = match reloc_target_alloc {
                 AllocType::Function(instance) => {
                     let (func_name, sig) = crate::abi::get_function_name_and_sig(tcx, instance);
                     let func_id = module
                         .declare_function(&func_name, Linkage::Import, &sig)
                         .unwrap();
                     let local_func_id = module.declare_func_in_data(func_id, &mut data_ctx);
-                    data_ctx.write_function_addr(reloc_offset as u32, local_func_id);
+                    data_ctx.write_function_addr(offset.bytes() as u32, local_func_id);
                     continue;
                 }
                 AllocType::Memory(_) => {
                     cx.todo.insert(TodoItem::Alloc(reloc));
-                    data_id_for_alloc_id(module, reloc)
+                    data_id_for_alloc_id(tcx, module, reloc)
                 }
                 AllocType::Static(def_id) => {
-                    cx.todo.insert(TodoItem::Static(def_id));
-                    data_id_for_static(tcx, module, def_id, Linkage::Import)
+                    // Local statics are mono items themselves, so they are defined by
+                    // `codegen_static`. Statics from other crates must not be defined here.
+                    data_id_for_static(tcx, module, cx, def_id, Linkage::Import)
                 }
             };
 
             let global_value = module.declare_data_in_data(data_id, &mut data_ctx);
-            data_ctx.write_data_addr(reloc_offset as u32, global_value, 0);
+            data_ctx.write_data_addr(offset.bytes() as u32, global_value, addend as i64);
         }
 
         module.define_data(data_id, &data_ctx).unwrap();
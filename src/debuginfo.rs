@@ -0,0 +1,940 @@
+use crate::prelude::*;
+
+use syntax::source_map::{FileName, Pos, Span};
+
+use cranelift::codegen::ir::{InstructionData, Opcode, ValueDef, ValueLabel, ValueLoc};
+use cranelift::codegen::isa::{RegUnit, TargetIsa};
+
+use gimli::write::{
+    Address, AttributeValue, DwarfUnit, EndianVec, Expression, FileId, LineProgram, LineString,
+    LineStringTable, Location, LocationList, Range, RangeList, Result, Sections, UnitEntryId,
+    Writer,
+};
+use gimli::{Encoding, Format, LineEncoding, RunTimeEndian, SectionId};
+
+use faerie::{Artifact, Decl, SectionKind};
+
+fn target_endian(tcx: TyCtxt) -> RunTimeEndian {
+    use rustc::ty::layout::Endian;
+
+    match tcx.data_layout.endian {
+        Endian::Big => RunTimeEndian::Big,
+        Endian::Little => RunTimeEndian::Little,
+    }
+}
+
+fn line_program_add_file(
+    line_program: &mut LineProgram,
+    line_strings: &mut LineStringTable,
+    file: &FileName,
+) -> FileId {
+    match file {
+        FileName::Real(path) => {
+            let encoding = line_program.encoding();
+            let dir_name = path.parent().unwrap().to_str().unwrap().as_bytes();
+            let dir_id = if !dir_name.is_empty() {
+                let dir_name = LineString::new(dir_name, encoding, line_strings);
+                line_program.add_directory(dir_name)
+            } else {
+                line_program.default_directory()
+            };
+            let file_name = LineString::new(
+                path.file_name().unwrap().to_str().unwrap().as_bytes(),
+                encoding,
+                line_strings,
+            );
+            line_program.add_file(file_name, dir_id, None)
+        }
+        // FIXME give more appropriate file names
+        _ => {
+            let encoding = line_program.encoding();
+            let dir_id = line_program.default_directory();
+            let dummy_file_name = LineString::new(file.to_string().into_bytes(), encoding, line_strings);
+            line_program.add_file(dummy_file_name, dir_id, None)
+        }
+    }
+}
+
+/// Fat pointers are pairs of the data pointer and the metadata, thin pointers a single scalar.
+fn is_thin_pointer(layout: TyLayout) -> bool {
+    match layout.abi {
+        Abi::Scalar(_) => true,
+        _ => false,
+    }
+}
+
+#[derive(Clone)]
+struct DebugReloc {
+    offset: u32,
+    size: u8,
+    name: DebugRelocName,
+    addend: i64,
+}
+
+#[derive(Clone)]
+enum DebugRelocName {
+    Section(SectionId),
+    Symbol(usize),
+}
+
+pub struct DebugContext<'tcx> {
+    endian: RunTimeEndian,
+    symbols: Vec<String>,
+
+    dwarf: DwarfUnit,
+    unit_range_list: RangeList,
+
+    types: HashMap<Ty<'tcx>, UnitEntryId>,
+}
+
+impl<'a, 'tcx: 'a> DebugContext<'tcx> {
+    pub fn new(tcx: TyCtxt, address_size: u8) -> Self {
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            // TODO: this should be configurable
+            // macOS doesn't seem to support DWARF > 3
+            version: 3,
+            address_size,
+        };
+
+        let mut dwarf = DwarfUnit::new(encoding);
+
+        let producer = "rustc_codegen_cranelift".to_string();
+        let comp_dir = tcx.sess.working_dir.0.to_string_lossy().into_owned();
+        let name = match tcx.sess.local_crate_source_file {
+            Some(ref path) => path.to_string_lossy().into_owned(),
+            None => tcx.crate_name(LOCAL_CRATE).to_string(),
+        };
+
+        let line_program = LineProgram::new(
+            encoding,
+            LineEncoding::default(),
+            LineString::new(comp_dir.as_bytes(), encoding, &mut dwarf.line_strings),
+            LineString::new(name.as_bytes(), encoding, &mut dwarf.line_strings),
+            None,
+        );
+        dwarf.unit.line_program = line_program;
+
+        {
+            let name = dwarf.strings.add(name);
+            let comp_dir = dwarf.strings.add(comp_dir);
+
+            let root = dwarf.unit.root();
+            let root = dwarf.unit.get_mut(root);
+            root.set(
+                gimli::DW_AT_producer,
+                AttributeValue::StringRef(dwarf.strings.add(producer)),
+            );
+            root.set(
+                gimli::DW_AT_language,
+                AttributeValue::Language(gimli::DW_LANG_Rust),
+            );
+            root.set(gimli::DW_AT_name, AttributeValue::StringRef(name));
+            root.set(gimli::DW_AT_comp_dir, AttributeValue::StringRef(comp_dir));
+            root.set(
+                gimli::DW_AT_low_pc,
+                AttributeValue::Address(Address::Constant(0)),
+            );
+        }
+
+        DebugContext {
+            endian: target_endian(tcx),
+            symbols: Vec::new(),
+
+            dwarf,
+            unit_range_list: RangeList(Vec::new()),
+
+            types: HashMap::new(),
+        }
+    }
+
+    fn emit_location(&mut self, tcx: TyCtxt<'a, 'tcx, 'tcx>, entry_id: UnitEntryId, span: Span) {
+        let loc = tcx.sess.source_map().lookup_char_pos(span.lo());
+
+        let file_id = line_program_add_file(
+            &mut self.dwarf.unit.line_program,
+            &mut self.dwarf.line_strings,
+            &loc.file.name,
+        );
+
+        let entry = self.dwarf.unit.get_mut(entry_id);
+
+        entry.set(
+            gimli::DW_AT_decl_file,
+            AttributeValue::FileIndex(Some(file_id)),
+        );
+        entry.set(
+            gimli::DW_AT_decl_line,
+            AttributeValue::Udata(loc.line as u64),
+        );
+        // FIXME: probably omit this
+        entry.set(
+            gimli::DW_AT_decl_column,
+            AttributeValue::Udata(loc.col.to_usize() as u64),
+        );
+    }
+
+    fn dwarf_ty(&mut self, tcx: TyCtxt<'a, 'tcx, 'tcx>, ty: Ty<'tcx>) -> UnitEntryId {
+        if let Some(type_id) = self.types.get(&ty) {
+            return *type_id;
+        }
+
+        let cx = layout::LayoutCx {
+            tcx,
+            param_env: ParamEnv::reveal_all(),
+        };
+        let layout = cx.layout_of(ty).unwrap();
+
+        let tag = match ty.sty {
+            ty::Bool | ty::Char | ty::Int(_) | ty::Uint(_) | ty::Float(_) => {
+                gimli::DW_TAG_base_type
+            }
+            ty::Ref(..) | ty::RawPtr(..) if is_thin_pointer(layout) => gimli::DW_TAG_pointer_type,
+            ty::FnPtr(_) => gimli::DW_TAG_pointer_type,
+            ty::Array(..) => gimli::DW_TAG_array_type,
+            _ => gimli::DW_TAG_structure_type,
+        };
+
+        // The entry is registered before describing the type, so recursive types can refer to
+        // themselves through pointers.
+        let root = self.dwarf.unit.root();
+        let type_id = self.dwarf.unit.add(root, tag);
+        self.types.insert(ty, type_id);
+
+        let name = self.dwarf.strings.add(format!("{}", ty));
+        let entry = self.dwarf.unit.get_mut(type_id);
+        entry.set(gimli::DW_AT_name, AttributeValue::StringRef(name));
+        entry.set(
+            gimli::DW_AT_byte_size,
+            AttributeValue::Udata(layout.size.bytes()),
+        );
+
+        let encoding = match ty.sty {
+            ty::Bool => Some(gimli::DW_ATE_boolean),
+            ty::Char => Some(gimli::DW_ATE_UTF),
+            ty::Int(_) => Some(gimli::DW_ATE_signed),
+            ty::Uint(_) => Some(gimli::DW_ATE_unsigned),
+            ty::Float(_) => Some(gimli::DW_ATE_float),
+            _ => None,
+        };
+        if let Some(encoding) = encoding {
+            entry.set(gimli::DW_AT_encoding, AttributeValue::Encoding(encoding));
+            return type_id;
+        }
+
+        match ty.sty {
+            ty::Ref(_, pointee_ty, _) | ty::RawPtr(ty::TypeAndMut { ty: pointee_ty, .. })
+                if is_thin_pointer(layout) =>
+            {
+                let pointee_id = self.dwarf_ty(tcx, pointee_ty);
+                self.dwarf.unit.get_mut(type_id).set(
+                    gimli::DW_AT_type,
+                    AttributeValue::ThisUnitEntryRef(pointee_id),
+                );
+            }
+            ty::Ref(..) | ty::RawPtr(..) => {
+                // Fat pointers are described as a pair of the data pointer and the metadata.
+                let field_names = match ty.builtin_deref(true).unwrap().ty.sty {
+                    ty::Dynamic(..) => ["pointer", "vtable"],
+                    _ => ["data_ptr", "length"],
+                };
+                self.add_fields(tcx, type_id, layout, Size::ZERO, |i| {
+                    field_names[i].to_string()
+                });
+            }
+            ty::FnPtr(_) => {}
+            ty::Array(elem_ty, len) => {
+                let elem_id = self.dwarf_ty(tcx, elem_ty);
+                let usize_id = self.dwarf_ty(tcx, tcx.types.usize);
+                self.dwarf.unit.get_mut(type_id).set(
+                    gimli::DW_AT_type,
+                    AttributeValue::ThisUnitEntryRef(elem_id),
+                );
+                let subrange_id = self.dwarf.unit.add(type_id, gimli::DW_TAG_subrange_type);
+                let subrange = self.dwarf.unit.get_mut(subrange_id);
+                subrange.set(gimli::DW_AT_type, AttributeValue::ThisUnitEntryRef(usize_id));
+                subrange.set(
+                    gimli::DW_AT_count,
+                    AttributeValue::Udata(len.unwrap_usize(tcx)),
+                );
+            }
+            ty::Adt(adt_def, _) if adt_def.is_enum() => {
+                self.add_enum_variants(tcx, type_id, layout);
+            }
+            ty::Adt(adt_def, _) => {
+                let variant = adt_def.non_enum_variant();
+                self.add_fields(tcx, type_id, layout, Size::ZERO, |i| {
+                    variant.fields[i].ident.to_string()
+                });
+            }
+            ty::Tuple(_) | ty::Closure(..) => {
+                self.add_fields(tcx, type_id, layout, Size::ZERO, |i| format!("__{}", i));
+            }
+            // Other types are only described by their name and size.
+            _ => {}
+        }
+
+        type_id
+    }
+
+    /// Add a member for every field of `layout` to the struct type `type_id`.
+    fn add_fields(
+        &mut self,
+        tcx: TyCtxt<'a, 'tcx, 'tcx>,
+        type_id: UnitEntryId,
+        layout: TyLayout<'tcx>,
+        base_offset: Size,
+        field_name: impl Fn(usize) -> String,
+    ) {
+        let cx = layout::LayoutCx {
+            tcx,
+            param_env: ParamEnv::reveal_all(),
+        };
+
+        for i in 0..layout.fields.count() {
+            let field_layout = layout.field(&cx, i).unwrap();
+            let field_type_id = self.dwarf_ty(tcx, field_layout.ty);
+            let name = self.dwarf.strings.add(field_name(i));
+
+            let member_id = self.dwarf.unit.add(type_id, gimli::DW_TAG_member);
+            let member = self.dwarf.unit.get_mut(member_id);
+            member.set(gimli::DW_AT_name, AttributeValue::StringRef(name));
+            member.set(
+                gimli::DW_AT_type,
+                AttributeValue::ThisUnitEntryRef(field_type_id),
+            );
+            member.set(
+                gimli::DW_AT_data_member_location,
+                AttributeValue::Udata((base_offset + layout.fields.offset(i)).bytes()),
+            );
+        }
+    }
+
+    /// Describe the variants of an enum using a `DW_TAG_variant_part`, the way gdb expects Rust
+    /// enums to be described.
+    fn add_enum_variants(
+        &mut self,
+        tcx: TyCtxt<'a, 'tcx, 'tcx>,
+        type_id: UnitEntryId,
+        layout: TyLayout<'tcx>,
+    ) {
+        let cx = layout::LayoutCx {
+            tcx,
+            param_env: ParamEnv::reveal_all(),
+        };
+        let adt_def = layout.ty.ty_adt_def().unwrap();
+        if adt_def.variants.is_empty() {
+            return;
+        }
+
+        let (tag_size, niche) = match layout.variants {
+            layout::Variants::Single { index } => {
+                let variant_layout = layout.for_variant(&cx, index);
+                let variant = &adt_def.variants[index];
+                self.add_fields(tcx, type_id, variant_layout, Size::ZERO, |i| {
+                    variant.fields[i].ident.to_string()
+                });
+                return;
+            }
+            layout::Variants::Tagged { ref tag, .. } => (tag.value.size(&cx), None),
+            layout::Variants::NicheFilling {
+                dataful_variant,
+                ref niche_variants,
+                ref niche,
+                niche_start,
+                ..
+            } => (
+                niche.value.size(&cx),
+                Some((dataful_variant, niche_variants.clone(), niche_start)),
+            ),
+        };
+        let tag_mask = !0u128 >> (128 - tag_size.bits());
+
+        let variant_part_id = self.dwarf.unit.add(type_id, gimli::DW_TAG_variant_part);
+
+        let tag_layout = layout.field(&cx, 0).unwrap();
+        let tag_type_id = self.dwarf_ty(tcx, tag_layout.ty);
+        let tag_member_id = self.dwarf.unit.add(variant_part_id, gimli::DW_TAG_member);
+        let tag_member = self.dwarf.unit.get_mut(tag_member_id);
+        tag_member.set(gimli::DW_AT_artificial, AttributeValue::Flag(true));
+        tag_member.set(
+            gimli::DW_AT_type,
+            AttributeValue::ThisUnitEntryRef(tag_type_id),
+        );
+        tag_member.set(
+            gimli::DW_AT_data_member_location,
+            AttributeValue::Udata(layout.fields.offset(0).bytes()),
+        );
+        self.dwarf.unit.get_mut(variant_part_id).set(
+            gimli::DW_AT_discr,
+            AttributeValue::ThisUnitEntryRef(tag_member_id),
+        );
+
+        for (variant_index, variant) in adt_def.variants.iter_enumerated() {
+            let variant_layout = layout.for_variant(&cx, variant_index);
+            if variant_layout.abi == Abi::Uninhabited {
+                continue;
+            }
+
+            // The discriminant is stored truncated to the size of the tag.
+            let discr = match niche {
+                None => {
+                    let discr = adt_def.discriminant_for_variant(tcx, variant_index).val;
+                    AttributeValue::Udata((discr & tag_mask) as u64)
+                }
+                Some((dataful_variant, ref niche_variants, niche_start)) => {
+                    let niche_start_index = niche_variants.start().as_u32();
+                    if variant_index == dataful_variant {
+                        // Every value outside of the niche belongs to the dataful variant.
+                        let first = niche_start & tag_mask;
+                        let last = (niche_variants.end().as_u32() - niche_start_index) as u128;
+                        let last = niche_start.wrapping_add(last) & tag_mask;
+                        let mut ranges = Vec::new();
+                        if first <= last {
+                            if first > 0 {
+                                ranges.push((0, first - 1));
+                            }
+                            if last < tag_mask {
+                                ranges.push((last + 1, tag_mask));
+                            }
+                        } else {
+                            ranges.push((last + 1, first - 1));
+                        }
+                        discr_list(&ranges)
+                    } else {
+                        let discr = ((variant_index.as_u32() - niche_start_index) as u128)
+                            .wrapping_add(niche_start);
+                        AttributeValue::Udata((discr & tag_mask) as u64)
+                    }
+                }
+            };
+
+            let variant_id = self.dwarf.unit.add(variant_part_id, gimli::DW_TAG_variant);
+            let discr_attr = match discr {
+                AttributeValue::Block(_) => gimli::DW_AT_discr_list,
+                _ => gimli::DW_AT_discr_value,
+            };
+            self.dwarf.unit.get_mut(variant_id).set(discr_attr, discr);
+
+            let variant_name = variant.name.to_string();
+            let variant_type_name = self
+                .dwarf
+                .strings
+                .add(format!("{}::{}", layout.ty, variant_name));
+            let root = self.dwarf.unit.root();
+            let variant_type_id = self.dwarf.unit.add(root, gimli::DW_TAG_structure_type);
+            let variant_type = self.dwarf.unit.get_mut(variant_type_id);
+            variant_type.set(
+                gimli::DW_AT_name,
+                AttributeValue::StringRef(variant_type_name),
+            );
+            variant_type.set(
+                gimli::DW_AT_byte_size,
+                AttributeValue::Udata(layout.size.bytes()),
+            );
+            self.add_fields(tcx, variant_type_id, variant_layout, Size::ZERO, |i| {
+                variant.fields[i].ident.to_string()
+            });
+
+            let variant_name = self.dwarf.strings.add(variant_name);
+            let member_id = self.dwarf.unit.add(variant_id, gimli::DW_TAG_member);
+            let member = self.dwarf.unit.get_mut(member_id);
+            member.set(gimli::DW_AT_name, AttributeValue::StringRef(variant_name));
+            member.set(
+                gimli::DW_AT_type,
+                AttributeValue::ThisUnitEntryRef(variant_type_id),
+            );
+            member.set(gimli::DW_AT_data_member_location, AttributeValue::Udata(0));
+        }
+    }
+
+    /// Drop the type information, which is only needed while translating.
+    pub fn finish(self) -> UnitDebugInfo {
+        UnitDebugInfo {
+            endian: self.endian,
+            symbols: self.symbols,
+
+            dwarf: self.dwarf,
+            unit_range_list: self.unit_range_list,
+        }
+    }
+}
+
+pub struct FunctionDebugContext<'a, 'tcx> {
+    debug_context: &'a mut DebugContext<'tcx>,
+    entry_id: UnitEntryId,
+    symbol: usize,
+    mir: &'tcx Mir<'tcx>,
+}
+
+impl<'a, 'b, 'tcx: 'b> FunctionDebugContext<'a, 'tcx> {
+    pub fn new(
+        tcx: TyCtxt<'b, 'tcx, 'tcx>,
+        debug_context: &'a mut DebugContext<'tcx>,
+        mir: &'tcx Mir<'tcx>,
+        name: &str,
+        _sig: &Signature,
+    ) -> Self {
+        let symbol = debug_context.symbols.len();
+        debug_context.symbols.push(name.to_string());
+
+        let dwarf = &mut debug_context.dwarf;
+        let scope = dwarf.unit.root();
+        let entry_id = dwarf.unit.add(scope, gimli::DW_TAG_subprogram);
+        let entry = dwarf.unit.get_mut(entry_id);
+        let name_id = dwarf.strings.add(name);
+        entry.set(gimli::DW_AT_name, AttributeValue::StringRef(name_id));
+        entry.set(gimli::DW_AT_linkage_name, AttributeValue::StringRef(name_id));
+
+        entry.set(
+            gimli::DW_AT_low_pc,
+            AttributeValue::Address(Address::Symbol { symbol, addend: 0 }),
+        );
+
+        debug_context.emit_location(tcx, entry_id, mir.span);
+
+        FunctionDebugContext {
+            debug_context,
+            entry_id,
+            symbol,
+            mir,
+        }
+    }
+
+    /// Add the user variables of the function and resolve the spans of `source_info_set`, which
+    /// maps the `SourceLoc`s set on the instructions back to MIR source infos. The rest of the
+    /// debuginfo needs the compiled function, see `UnitDebugInfo::define_function`.
+    pub fn finish(
+        self,
+        tcx: TyCtxt<'b, 'tcx, 'tcx>,
+        local_map: &HashMap<Local, CPlace<'tcx>>,
+        source_info_set: &indexmap::IndexSet<SourceInfo>,
+    ) -> FunctionDebugInfo {
+        let (source_rows, function_row) = {
+            let line_strings = &mut self.debug_context.dwarf.line_strings;
+            let line_program = &mut self.debug_context.dwarf.unit.line_program;
+            let mut span_to_row = |span: Span| {
+                let loc = tcx.sess.source_map().lookup_char_pos(span.lo());
+                SourceRow {
+                    file_id: line_program_add_file(line_program, line_strings, &loc.file.name),
+                    line: loc.line as u64,
+                    column: loc.col.to_usize() as u64 + 1,
+                }
+            };
+            let source_rows = source_info_set
+                .iter()
+                .map(|source_info| span_to_row(source_info.span))
+                .collect();
+            (source_rows, span_to_row(self.mir.span))
+        };
+
+        let mut variables = Vec::new();
+        for local in self.mir.args_iter().chain(self.mir.vars_and_temps_iter()) {
+            // Only user variables and named arguments have a name.
+            let name = match self.mir.local_decls[local].name {
+                Some(name) => name,
+                None => continue,
+            };
+            let place = local_map[&local];
+
+            let tag = if local.index() <= self.mir.arg_count {
+                gimli::DW_TAG_formal_parameter
+            } else {
+                gimli::DW_TAG_variable
+            };
+            let type_id = self.debug_context.dwarf_ty(tcx, place.layout().ty);
+            let name_id = self.debug_context.dwarf.strings.add(name.as_str().to_string());
+
+            let var_id = self.debug_context.dwarf.unit.add(self.entry_id, tag);
+            let var_entry = self.debug_context.dwarf.unit.get_mut(var_id);
+            var_entry.set(gimli::DW_AT_name, AttributeValue::StringRef(name_id));
+            var_entry.set(gimli::DW_AT_type, AttributeValue::ThisUnitEntryRef(type_id));
+
+            let location = match place {
+                CPlace::Var(local, _) => {
+                    VariableLocation::Var(ValueLabel::from_u32(local.index() as u32))
+                }
+                CPlace::Addr(addr, None, _) => VariableLocation::Addr(addr),
+                CPlace::Addr(_, Some(_), _) => continue,
+            };
+            variables.push((var_id, location));
+        }
+
+        FunctionDebugInfo {
+            entry_id: self.entry_id,
+            symbol: self.symbol,
+            source_rows,
+            function_row,
+            variables,
+        }
+    }
+}
+
+/// The debuginfo of a codegen unit after all its functions have been translated. Unlike
+/// `DebugContext` it doesn't depend on the `TyCtxt`, so the workers of `crate::emit_pool` can
+/// complete it while compiling the functions.
+pub struct UnitDebugInfo {
+    endian: RunTimeEndian,
+    symbols: Vec<String>,
+
+    dwarf: DwarfUnit,
+    unit_range_list: RangeList,
+}
+
+/// The debuginfo of a translated function, which can only be completed once it is compiled.
+pub struct FunctionDebugInfo {
+    entry_id: UnitEntryId,
+    symbol: usize,
+    /// The line table row of each source info, indexed by the `SourceLoc`s of the instructions
+    source_rows: Vec<SourceRow>,
+    /// The line table row of instructions without a `SourceLoc`
+    function_row: SourceRow,
+    variables: Vec<(UnitEntryId, VariableLocation)>,
+}
+
+#[derive(Copy, Clone)]
+struct SourceRow {
+    file_id: FileId,
+    line: u64,
+    column: u64,
+}
+
+enum VariableLocation {
+    /// Stored in an ssa variable labeled with the local
+    Var(ValueLabel),
+    /// Stored on the stack at the given address
+    Addr(Value),
+}
+
+impl UnitDebugInfo {
+    /// Emit the line table rows, the code range and the variable locations of a function after it
+    /// has been compiled.
+    pub fn define_function(
+        &mut self,
+        isa: &dyn TargetIsa,
+        context: &Context,
+        function: FunctionDebugInfo,
+    ) {
+        let func = &context.func;
+        let encinfo = isa.encoding_info();
+
+        let line_program = &mut self.dwarf.unit.line_program;
+
+        line_program.begin_sequence(Some(Address::Symbol {
+            symbol: function.symbol,
+            addend: 0,
+        }));
+
+        let mut end = 0;
+        for ebb in func.layout.ebbs() {
+            for (offset, inst, size) in func.inst_offsets(ebb, &encinfo) {
+                let srcloc = func.srclocs[inst];
+                let row = if !srcloc.is_default() {
+                    function.source_rows[srcloc.bits() as usize]
+                } else {
+                    function.function_row
+                };
+                line_program.row().address_offset = u64::from(offset);
+                line_program.row().file = row.file_id;
+                line_program.row().line = row.line;
+                line_program.row().column = row.column;
+                line_program.generate_row();
+                end = offset + size;
+            }
+        }
+
+        line_program.end_sequence(u64::from(end));
+
+        let entry = self.dwarf.unit.get_mut(function.entry_id);
+        entry.set(
+            gimli::DW_AT_high_pc,
+            AttributeValue::Address(Address::Symbol {
+                symbol: function.symbol,
+                addend: i64::from(end),
+            }),
+        );
+
+        self.unit_range_list.0.push(Range::StartLength {
+            begin: Address::Symbol {
+                symbol: function.symbol,
+                addend: 0,
+            },
+            length: u64::from(end),
+        });
+
+        // Variable locations are only known for x86_64.
+        if isa.name() != "x86" || isa.pointer_bits() != 64 {
+            return;
+        }
+
+        // Cranelift always uses rbp as frame pointer on x86_64, so the CFA is at rbp + 16.
+        let mut frame_base = vec![gimli::DW_OP_breg6.0];
+        gimli::leb128::write::signed(&mut frame_base, 16).unwrap();
+        self.dwarf.unit.get_mut(function.entry_id).set(
+            gimli::DW_AT_frame_base,
+            AttributeValue::Exprloc(Expression(frame_base)),
+        );
+
+        let value_labels_ranges = context.build_value_labels_ranges(isa).unwrap();
+
+        for (var_id, location) in function.variables {
+            let location = match location {
+                VariableLocation::Var(value_label) => {
+                    let ranges = match value_labels_ranges.get(&value_label) {
+                        Some(ranges) => ranges,
+                        None => continue,
+                    };
+                    let symbol = function.symbol;
+                    let location_list = LocationList(
+                        ranges
+                            .iter()
+                            .filter_map(|range| {
+                                Some(Location::StartEnd {
+                                    begin: Address::Symbol {
+                                        symbol,
+                                        addend: i64::from(range.start),
+                                    },
+                                    end: Address::Symbol {
+                                        symbol,
+                                        addend: i64::from(range.end),
+                                    },
+                                    data: translate_value_loc(func, range.loc)?,
+                                })
+                            })
+                            .collect(),
+                    );
+                    let location_list_id = self.dwarf.unit.locations.add(location_list);
+                    AttributeValue::LocationListRef(location_list_id)
+                }
+                VariableLocation::Addr(addr) => match stack_addr_location(func, addr) {
+                    Some(location) => AttributeValue::Exprloc(location),
+                    None => continue,
+                },
+            };
+            self.dwarf
+                .unit
+                .get_mut(var_id)
+                .set(gimli::DW_AT_location, location);
+        }
+    }
+
+    /// Write the debug sections to `artifact` and link them to the functions they describe.
+    pub fn emit(&mut self, artifact: &mut Artifact) {
+        let unit_range_list_id = self
+            .dwarf
+            .unit
+            .ranges
+            .add(self.unit_range_list.clone());
+        let root = self.dwarf.unit.root();
+        self.dwarf.unit.get_mut(root).set(
+            gimli::DW_AT_ranges,
+            AttributeValue::RangeListRef(unit_range_list_id),
+        );
+
+        let mut sections = Sections::new(WriterRelocate::new(self));
+        self.dwarf.write(&mut sections).unwrap();
+
+        let mut section_data = Vec::new();
+        let _: Result<()> = sections.for_each_mut(|id, section| {
+            if !section.writer.slice().is_empty() {
+                section_data.push((id, section.writer.slice().to_vec(), section.relocs.clone()));
+            }
+            Ok(())
+        });
+
+        // All sections need to be declared before any relocations between them can be added.
+        for (id, data, _) in &section_data {
+            artifact
+                .declare_with(id.name(), Decl::section(SectionKind::Debug), data.clone())
+                .unwrap();
+        }
+
+        for (id, _, relocs) in &section_data {
+            for reloc in relocs {
+                let name = match reloc.name {
+                    DebugRelocName::Section(id) => id.name(),
+                    DebugRelocName::Symbol(index) => &self.symbols[index][..],
+                };
+                artifact
+                    .link_with(
+                        faerie::Link {
+                            from: id.name(),
+                            to: name,
+                            at: u64::from(reloc.offset),
+                        },
+                        faerie::Reloc::Debug {
+                            size: reloc.size,
+                            addend: reloc.addend as i32,
+                        },
+                    )
+                    .expect("faerie relocation error");
+            }
+        }
+    }
+}
+
+/// Returns the frame base relative address of a local stored on the stack at `addr`.
+fn stack_addr_location(func: &Function, mut addr: Value) -> Option<Expression> {
+    // The register allocator may have moved the stack address through spills and fills, so look
+    // through them to find the `stack_addr` it originates from.
+    let inst = loop {
+        let inst = match func.dfg.value_def(addr) {
+            ValueDef::Result(inst, _) => inst,
+            ValueDef::Param(_, _) => return None,
+        };
+        match func.dfg[inst] {
+            InstructionData::Unary {
+                opcode: Opcode::Spill,
+                arg,
+            }
+            | InstructionData::Unary {
+                opcode: Opcode::Fill,
+                arg,
+            }
+            | InstructionData::Unary {
+                opcode: Opcode::Copy,
+                arg,
+            } => addr = arg,
+            _ => break inst,
+        }
+    };
+    match func.dfg[inst] {
+        InstructionData::StackLoad {
+            opcode: Opcode::StackAddr,
+            stack_slot,
+            offset,
+        } => {
+            let slot_offset = func.stack_slots[stack_slot].offset?;
+            let offset: i32 = offset.into();
+            Some(frame_base_offset(
+                i64::from(slot_offset) + i64::from(offset),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Encode the inclusive `ranges` of discriminant values as the block of a `DW_AT_discr_list`.
+fn discr_list(ranges: &[(u128, u128)]) -> AttributeValue {
+    let mut block = Vec::new();
+    for &(low, high) in ranges {
+        block.push(gimli::DW_DSC_range.0);
+        gimli::leb128::write::unsigned(&mut block, low as u64).unwrap();
+        gimli::leb128::write::unsigned(&mut block, high as u64).unwrap();
+    }
+    AttributeValue::Block(block)
+}
+
+fn frame_base_offset(offset: i64) -> Expression {
+    let mut expr = vec![gimli::DW_OP_fbreg.0];
+    gimli::leb128::write::signed(&mut expr, offset).unwrap();
+    Expression(expr)
+}
+
+fn translate_value_loc(func: &Function, loc: ValueLoc) -> Option<Expression> {
+    match loc {
+        ValueLoc::Reg(reg) => {
+            let dwarf_reg = x86_64_dwarf_register(reg)?;
+            let mut expr = vec![gimli::DW_OP_regx.0];
+            gimli::leb128::write::unsigned(&mut expr, u64::from(dwarf_reg)).unwrap();
+            Some(Expression(expr))
+        }
+        ValueLoc::Stack(stack_slot) => {
+            let slot_offset = func.stack_slots[stack_slot].offset?;
+            Some(frame_base_offset(i64::from(slot_offset)))
+        }
+        ValueLoc::Unassigned => None,
+    }
+}
+
+fn x86_64_dwarf_register(reg: RegUnit) -> Option<u16> {
+    // Cranelift numbers the general purpose registers in encoding order (rax, rcx, rdx, rbx, rsp,
+    // rbp, rsi, rdi), while DWARF uses (rax, rdx, rcx, rbx, rsi, rdi, rbp, rsp).
+    const GPRS: [u16; 16] = [0, 2, 1, 3, 7, 6, 4, 5, 8, 9, 10, 11, 12, 13, 14, 15];
+    match reg {
+        0..=15 => Some(GPRS[reg as usize]),
+        // xmm0 - xmm15
+        16..=31 => Some(17 + (reg - 16)),
+        _ => None,
+    }
+}
+
+/// A gimli writer which records the relocations needed for addresses and section offsets, as
+/// the final values are only known once the object file is linked.
+#[derive(Clone)]
+struct WriterRelocate {
+    relocs: Vec<DebugReloc>,
+    writer: EndianVec<RunTimeEndian>,
+}
+
+impl WriterRelocate {
+    fn new(ctx: &UnitDebugInfo) -> Self {
+        WriterRelocate {
+            relocs: Vec::new(),
+            writer: EndianVec::new(ctx.endian),
+        }
+    }
+}
+
+impl Writer for WriterRelocate {
+    type Endian = RunTimeEndian;
+
+    fn endian(&self) -> Self::Endian {
+        self.writer.endian()
+    }
+
+    fn len(&self) -> usize {
+        self.writer.len()
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write(bytes)
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
+        self.writer.write_at(offset, bytes)
+    }
+
+    fn write_address(&mut self, address: Address, size: u8) -> Result<()> {
+        match address {
+            Address::Constant(val) => self.write_udata(val, size),
+            Address::Symbol { symbol, addend } => {
+                let offset = self.len() as u64;
+                self.relocs.push(DebugReloc {
+                    offset: offset as u32,
+                    size,
+                    name: DebugRelocName::Symbol(symbol),
+                    addend,
+                });
+                self.write_udata(0, size)
+            }
+        }
+    }
+
+    fn write_offset(&mut self, val: usize, section: SectionId, size: u8) -> Result<()> {
+        let offset = self.len() as u32;
+        self.relocs.push(DebugReloc {
+            offset,
+            size,
+            name: DebugRelocName::Section(section),
+            addend: val as i64,
+        });
+        self.write_udata(0, size)
+    }
+
+    fn write_offset_at(
+        &mut self,
+        offset: usize,
+        val: usize,
+        section: SectionId,
+        size: u8,
+    ) -> Result<()> {
+        self.relocs.push(DebugReloc {
+            offset: offset as u32,
+            size,
+            name: DebugRelocName::Section(section),
+            addend: val as i64,
+        });
+        self.write_udata_at(offset, 0, size)
+    }
+}
@@ -0,0 +1,156 @@
+//! The `TyCtxt` can't be shared between threads, so the MIR of the codegen units is translated to
+//! Cranelift IR one after another on the main thread. Compiling the IR and writing the objects
+//! doesn't need it, so this is done by a pool of worker threads in the meantime. Writing an object
+//! consists of compiling its functions, completing their debuginfo, emitting the ELF file,
+//! patching it with `crate::object_patch` and assembling the global asm with the system
+//! assembler.
+
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use cranelift_faerie::FaerieBackend;
+
+use crate::debuginfo::{FunctionDebugInfo, UnitDebugInfo};
+use crate::object_patch::ObjectPatches;
+use crate::prelude::*;
+
+/// A function translated on the main thread, which is compiled by a worker.
+pub struct PendingFunction {
+    pub name: String,
+    pub func_id: FuncId,
+    pub func: Function,
+    pub debug_info: Option<FunctionDebugInfo>,
+}
+
+/// A module which is moved to a worker to compile its pending functions.
+pub struct SendModule(pub Module<FaerieBackend>);
+
+// The `TargetIsa` and the libcall names of a `FaerieBackend` are trait objects without a `Send`
+// bound. The ones created by `crate::new_module` don't share any state with other threads.
+unsafe impl Send for SendModule {}
+
+pub enum EmitJob {
+    Module {
+        module: SendModule,
+        pending_functions: Vec<PendingFunction>,
+        object_patches: ObjectPatches,
+        debug: Option<UnitDebugInfo>,
+        obj_path: PathBuf,
+    },
+    GlobalAsm {
+        asm: String,
+        asm_path: PathBuf,
+        obj_path: PathBuf,
+    },
+}
+
+impl EmitJob {
+    fn run(self) -> Result<(), String> {
+        match self {
+            EmitJob::Module {
+                module: SendModule(mut module),
+                pending_functions,
+                object_patches,
+                mut debug,
+                obj_path,
+            } => {
+                let mut ctx = Context::new();
+                for PendingFunction {
+                    name,
+                    func_id,
+                    func,
+                    debug_info,
+                } in pending_functions
+                {
+                    ctx.func = func;
+                    module
+                        .define_function(func_id, &mut ctx)
+                        .map_err(|err| format!("Failed to compile {}: {}", name, err))?;
+                    if let (Some(debug), Some(debug_info)) = (&mut debug, debug_info) {
+                        debug.define_function(module.isa(), &ctx, debug_info);
+                    }
+                    ctx.clear();
+                }
+                module.finalize_definitions();
+                let mut artifact = module.finish().artifact;
+                if let Some(mut debug) = debug {
+                    debug.emit(&mut artifact);
+                }
+
+                let obj = artifact
+                    .emit()
+                    .map_err(|err| format!("Failed to emit object {}: {}", obj_path.display(), err))?;
+                let obj = crate::object_patch::patch_object(obj, &object_patches)?;
+                std::fs::write(&obj_path, obj)
+                    .map_err(|err| format!("Failed to write {}: {}", obj_path.display(), err))
+            }
+            EmitJob::GlobalAsm {
+                asm,
+                asm_path,
+                obj_path,
+            } => crate::global_asm::assemble(&asm, &asm_path, &obj_path),
+        }
+    }
+}
+
+pub struct EmitPool {
+    sender: Sender<EmitJob>,
+    workers: Vec<JoinHandle<Vec<String>>>,
+}
+
+impl EmitPool {
+    pub fn new(num_workers: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..num_workers.max(1))
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("cg_clif emit {}", i))
+                    .spawn(move || worker(&receiver))
+                    .unwrap()
+            })
+            .collect();
+        EmitPool { sender, workers }
+    }
+
+    pub fn submit(&self, job: EmitJob) {
+        self.sender.send(job).unwrap();
+    }
+
+    /// Waits until all submitted jobs are done.
+    pub fn join(self, sess: &Session) {
+        let EmitPool { sender, workers } = self;
+        drop(sender);
+        for worker in workers {
+            match worker.join() {
+                Ok(errors) => {
+                    for err in errors {
+                        sess.err(&err);
+                    }
+                }
+                Err(_) => bug!("emit worker panicked"),
+            }
+        }
+        sess.abort_if_errors();
+    }
+}
+
+/// Runs jobs until the pool is joined and returns the errors of all failed jobs. Failing jobs
+/// don't stop the worker, as the remaining jobs would never be run otherwise.
+fn worker(receiver: &Mutex<Receiver<EmitJob>>) -> Vec<String> {
+    let mut errors = Vec::new();
+    loop {
+        let job = receiver.lock().unwrap().recv();
+        match job {
+            Ok(job) => {
+                if let Err(err) = job.run() {
+                    errors.push(err);
+                }
+            }
+            Err(mpsc::RecvError) => return errors,
+        }
+    }
+}
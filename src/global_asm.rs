@@ -0,0 +1,80 @@
+//! `global_asm!` is passed through to the system assembler, because Cranelift doesn't contain an
+//! assembler. The helpers generated for inline asm by `crate::inline_asm` are assembled together
+//! with it.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rustc::hir::ItemKind;
+use rustc::session::config::OutputType;
+use syntax::ast::NodeId;
+
+use crate::emit_pool::{EmitJob, EmitPool};
+use crate::prelude::*;
+
+pub fn codegen_global_asm<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    global_asm: &mut String,
+    node_id: NodeId,
+) {
+    let item = tcx.hir.expect_item(node_id);
+    if let ItemKind::GlobalAsm(ref ga) = item.node {
+        global_asm.push_str(&*ga.asm.as_str());
+        global_asm.push_str("\n\n");
+    } else {
+        bug!("Expected GlobalAsm found {:?}", item);
+    }
+}
+
+/// Submits the global asm of a codegen unit to the `EmitPool` for assembling. Returns the
+/// module of the resulting object, if there is any global asm.
+pub fn compile_global_asm<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    emit_pool: &EmitPool,
+    cgu_name: &str,
+    global_asm: String,
+) -> Option<CompiledModule> {
+    if global_asm.is_empty() {
+        return None;
+    }
+
+    let name = format!("{}.asm", cgu_name);
+    let output_filenames = tcx.output_filenames(LOCAL_CRATE);
+    let asm_path: PathBuf = output_filenames.temp_path_ext("s", Some(&name));
+    let obj_path = output_filenames.temp_path(OutputType::Object, Some(&name));
+    emit_pool.submit(EmitJob::GlobalAsm {
+        asm: global_asm,
+        asm_path,
+        obj_path: obj_path.clone(),
+    });
+
+    Some(CompiledModule {
+        name,
+        kind: ModuleKind::Regular,
+        object: Some(obj_path),
+        bytecode: None,
+        bytecode_compressed: None,
+    })
+}
+
+pub fn assemble(asm: &str, asm_path: &Path, obj_path: &Path) -> Result<(), String> {
+    std::fs::write(asm_path, asm)
+        .map_err(|err| format!("Failed to write {}: {}", asm_path.display(), err))?;
+
+    let status = Command::new("as")
+        .arg("-o")
+        .arg(obj_path)
+        .arg(asm_path)
+        .status();
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!(
+            "Failed to assemble global and inline asm: as exited with {}",
+            status
+        )),
+        Err(err) => Err(format!(
+            "Failed to assemble global and inline asm: couldn't run as: {}",
+            err
+        )),
+    }
+}
@@ -0,0 +1,419 @@
+//! Cranelift can't express inline assembly, so every `asm!` block is outlined into a helper
+//! function, which is appended to the global asm of the crate and assembled by the system
+//! assembler. The helper gets a pointer to a block of 8 byte slots, one per operand (outputs
+//! first, then inputs). It loads the inputs into the registers chosen for them, executes the asm
+//! template and stores the outputs back into the block.
+//!
+//! Only x86_64 general purpose register and memory operands are supported.
+
+use std::fmt::Write;
+
+use rustc::hir;
+use syntax::ast::AsmDialect;
+use syntax::source_map::Span;
+
+use crate::prelude::*;
+
+/// (64 bit, 32 bit, 16 bit, 8 bit) names of the registers operands can be allocated to.
+/// `rbp` and `rsp` are used by the helper itself.
+const GPRS: [[&str; 4]; 14] = [
+    ["rax", "eax", "ax", "al"],
+    ["rcx", "ecx", "cx", "cl"],
+    ["rdx", "edx", "dx", "dl"],
+    ["rsi", "esi", "si", "sil"],
+    ["rdi", "edi", "di", "dil"],
+    ["r8", "r8d", "r8w", "r8b"],
+    ["r9", "r9d", "r9w", "r9b"],
+    ["r10", "r10d", "r10w", "r10b"],
+    ["r11", "r11d", "r11w", "r11b"],
+    ["rbx", "ebx", "bx", "bl"],
+    ["r12", "r12d", "r12w", "r12b"],
+    ["r13", "r13d", "r13w", "r13b"],
+    ["r14", "r14d", "r14w", "r14b"],
+    ["r15", "r15d", "r15w", "r15b"],
+];
+
+/// Callee saved registers, which are saved by the helper, so the asm block may clobber them.
+const CALLEE_SAVED: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+
+/// Offset of the saved block pointer from `rbp` after the prologue of the helper, which pushes
+/// the `CALLEE_SAVED` registers.
+const BLOCK_PTR_OFFSET: i32 = -8 * (5 + 1);
+
+#[derive(Copy, Clone, Debug)]
+enum ConstraintKind {
+    /// Any general purpose register
+    Reg,
+    /// The given general purpose register
+    FixedReg(usize),
+    /// Memory operand. The address is passed in a general purpose register.
+    Mem,
+    /// Same location as the given output
+    Tied(usize),
+}
+
+struct AsmOperand {
+    kind: ConstraintKind,
+    reg: Option<usize>,
+    size: u64,
+    /// Whether the slot of this operand needs to be loaded into its register
+    load: bool,
+    /// Whether the register of this operand needs to be stored into its slot
+    store: bool,
+}
+
+fn reg_by_name(name: &str) -> Option<usize> {
+    let name = name.trim_start_matches('%');
+    GPRS.iter().position(|names| names.contains(&name))
+}
+
+fn parse_constraint(constraint: &str) -> ConstraintKind {
+    let constraint =
+        constraint.trim_start_matches(|c| c == '=' || c == '+' || c == '&' || c == '*');
+    if constraint.starts_with('{') && constraint.ends_with('}') {
+        let reg = &constraint[1..constraint.len() - 1];
+        return ConstraintKind::FixedReg(
+            reg_by_name(reg)
+                .unwrap_or_else(|| unimpl!("Unsupported inline asm register {}", reg)),
+        );
+    }
+    if let Ok(idx) = constraint.parse::<usize>() {
+        return ConstraintKind::Tied(idx);
+    }
+    match constraint {
+        "r" | "q" | "R" => ConstraintKind::Reg,
+        "a" => ConstraintKind::FixedReg(reg_by_name("rax").unwrap()),
+        "b" => ConstraintKind::FixedReg(reg_by_name("rbx").unwrap()),
+        "c" => ConstraintKind::FixedReg(reg_by_name("rcx").unwrap()),
+        "d" => ConstraintKind::FixedReg(reg_by_name("rdx").unwrap()),
+        "S" => ConstraintKind::FixedReg(reg_by_name("rsi").unwrap()),
+        "D" => ConstraintKind::FixedReg(reg_by_name("rdi").unwrap()),
+        "m" => ConstraintKind::Mem,
+        _ => unimpl!("Unsupported inline asm constraint {:?}", constraint),
+    }
+}
+
+pub fn codegen_inline_asm<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    asm: &hir::InlineAsm,
+    outputs: &[Place<'tcx>],
+    inputs: &[(Span, Operand<'tcx>)],
+) {
+    let output_places = outputs
+        .iter()
+        .map(|place| trans_place(fx, place))
+        .collect::<Vec<_>>();
+    let input_values = inputs
+        .iter()
+        .map(|(_span, operand)| trans_operand(fx, operand))
+        .collect::<Vec<_>>();
+
+    let mut operands = Vec::new();
+    for (output, place) in asm.outputs.iter().zip(output_places.iter()) {
+        let kind = parse_constraint(&*output.constraint.as_str());
+        operands.push(AsmOperand {
+            kind,
+            reg: None,
+            size: place.layout().size.bytes(),
+            load: output.is_rw || output.is_indirect,
+            store: !output.is_indirect,
+        });
+    }
+    for (constraint, value) in asm.inputs.iter().zip(input_values.iter()) {
+        let kind = parse_constraint(&*constraint.as_str());
+        operands.push(AsmOperand {
+            kind,
+            reg: None,
+            size: value.layout().size.bytes(),
+            load: true,
+            store: false,
+        });
+    }
+
+    let clobbered_regs = asm
+        .clobbers
+        .iter()
+        .filter_map(|clobber| {
+            match clobber.as_str().trim_matches(|c| c == '{' || c == '}') {
+                // Handled by the opaque call to the helper, see below.
+                "memory" | "cc" => None,
+                clobber => reg_by_name(clobber),
+            }
+        })
+        .collect::<Vec<_>>();
+    allocate_registers(&mut operands, &clobbered_regs);
+
+    // Write the operands into the operand block
+    let block_slot = fx.bcx.create_stack_slot(StackSlotData {
+        kind: StackSlotKind::ExplicitSlot,
+        size: 8 * operands.len() as u32,
+        offset: None,
+    });
+    let block = fx.bcx.ins().stack_addr(fx.pointer_type, block_slot, 0);
+    for (i, (output, place)) in asm.outputs.iter().zip(output_places.iter()).enumerate() {
+        let offset = 8 * i as i32;
+        if output.is_indirect {
+            let addr = place.expect_addr();
+            fx.bcx.ins().store(MemFlags::new(), addr, block, offset);
+        } else {
+            check_reg_operand(fx, place.layout());
+            if output.is_rw {
+                let value = place.to_cvalue(fx).load_value(fx);
+                fx.bcx.ins().store(MemFlags::new(), value, block, offset);
+            }
+        }
+    }
+    for (i, value) in input_values.into_iter().enumerate() {
+        let idx = asm.outputs.len() + i;
+        let value = match operands[idx].kind {
+            ConstraintKind::Mem => value.force_stack(fx),
+            _ => {
+                check_reg_operand(fx, value.layout());
+                value.load_value(fx)
+            }
+        };
+        fx.bcx
+            .ins()
+            .store(MemFlags::new(), value, block, 8 * idx as i32);
+    }
+
+    // Call the outlined asm block. Cranelift treats the call as opaque: it may read and write
+    // any memory and clobbers the flags and all caller saved registers. This is what the
+    // `memory` and `cc` clobbers require, so they need no further handling. Loads and stores must
+    // never be moved across this call, so it must stay a regular call.
+    let helper_name = format!(
+        "__inline_asm_{}_n{}",
+        sanitize_symbol(&fx.tcx.symbol_name(fx.instance).as_str()),
+        fx.inline_asm_index,
+    );
+    fx.inline_asm_index += 1;
+    let helper = generate_asm_wrapper(&helper_name, asm, &operands);
+    fx.global_asm.push_str(&helper);
+    let pointer_type = fx.pointer_type;
+    fx.lib_call(&helper_name, vec![pointer_type], None, &[block]);
+
+    // Read the outputs back from the operand block
+    for (i, (output, place)) in asm.outputs.iter().zip(output_places.into_iter()).enumerate() {
+        if output.is_indirect {
+            continue;
+        }
+        let layout = place.layout();
+        let clif_ty = fx.clif_type(layout.ty).unwrap();
+        let value = fx
+            .bcx
+            .ins()
+            .load(clif_ty, MemFlags::new(), block, 8 * i as i32);
+        place.write_cvalue(fx, CValue::ByVal(value, layout));
+    }
+}
+
+/// Symbol names may contain characters like `$`, which have a special meaning in assembly.
+fn sanitize_symbol(symbol: &str) -> String {
+    symbol
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '.' { c } else { '_' })
+        .collect()
+}
+
+fn check_reg_operand<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    layout: TyLayout<'tcx>,
+) {
+    match fx.clif_type(layout.ty) {
+        Some(ty) if ty.is_int() && ty.bits() <= 64 => {}
+        _ => unimpl!(
+            "Unsupported type {:?} for inline asm register operand",
+            layout.ty
+        ),
+    }
+}
+
+fn allocate_registers(operands: &mut [AsmOperand], clobbered_regs: &[usize]) {
+    let mut used = clobbered_regs.to_vec();
+    for operand in operands.iter_mut() {
+        if let ConstraintKind::FixedReg(reg) = operand.kind {
+            operand.reg = Some(reg);
+            used.push(reg);
+        }
+    }
+    for operand in operands.iter_mut() {
+        match operand.kind {
+            ConstraintKind::Reg | ConstraintKind::Mem => {
+                let reg = (0..GPRS.len())
+                    .find(|reg| !used.contains(reg))
+                    .unwrap_or_else(|| unimpl!("Ran out of registers for inline asm operands"));
+                operand.reg = Some(reg);
+                used.push(reg);
+            }
+            _ => {}
+        }
+    }
+    for i in 0..operands.len() {
+        if let ConstraintKind::Tied(output) = operands[i].kind {
+            match operands.get(output).map(|output| output.kind) {
+                Some(ConstraintKind::Reg) | Some(ConstraintKind::FixedReg(_)) => {}
+                _ => unimpl!("Inline asm operand {} tied to unsupported operand {}", i, output),
+            }
+            operands[i].reg = operands[output].reg;
+        }
+    }
+}
+
+fn reg_name(reg: usize, size: u64) -> &'static str {
+    match size {
+        8 => GPRS[reg][0],
+        4 => GPRS[reg][1],
+        2 => GPRS[reg][2],
+        1 => GPRS[reg][3],
+        _ => unimpl!("Unsupported inline asm operand size {}", size),
+    }
+}
+
+/// Find a register not used by any of the given operands, to hold the block pointer.
+fn scratch_reg<'a>(operands: impl Iterator<Item = &'a AsmOperand> + Clone) -> &'static str {
+    (0..GPRS.len())
+        .find(|&reg| !operands.clone().any(|operand| operand.reg == Some(reg)))
+        .map(|reg| GPRS[reg][0])
+        .unwrap_or_else(|| unimpl!("Ran out of registers for inline asm operands"))
+}
+
+fn format_operand(dialect: AsmDialect, operand: &AsmOperand) -> String {
+    let reg = operand.reg.unwrap();
+    match (dialect, operand.kind) {
+        (AsmDialect::Att, ConstraintKind::Mem) => format!("(%{})", GPRS[reg][0]),
+        (AsmDialect::Intel, ConstraintKind::Mem) => format!("[{}]", GPRS[reg][0]),
+        (AsmDialect::Att, _) => format!("%{}", reg_name(reg, operand.size)),
+        (AsmDialect::Intel, _) => reg_name(reg, operand.size).to_string(),
+    }
+}
+
+/// Replace the `$N` and `${N:modifier}` placeholders in the asm template with the operands.
+fn expand_template(asm: &hir::InlineAsm, operands: &[AsmOperand]) -> String {
+    let template = asm.asm.as_str();
+    let mut expanded = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+        let operand_idx = match chars.peek().cloned() {
+            Some('$') => {
+                chars.next();
+                expanded.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut placeholder = String::new();
+                while let Some(c) = chars.next() {
+                    if c == '}' {
+                        break;
+                    }
+                    placeholder.push(c);
+                }
+                let mut parts = placeholder.splitn(2, ':');
+                let idx = parts.next().unwrap();
+                if let Some(modifier) = parts.next() {
+                    unimpl!("Unsupported inline asm operand modifier {:?}", modifier);
+                }
+                idx.parse::<usize>()
+                    .unwrap_or_else(|_| unimpl!("Invalid inline asm operand {:?}", idx))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut idx = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    idx.push(c);
+                    chars.next();
+                }
+                idx.parse::<usize>().unwrap()
+            }
+            _ => {
+                expanded.push('$');
+                continue;
+            }
+        };
+        let operand = operands
+            .get(operand_idx)
+            .unwrap_or_else(|| unimpl!("Invalid inline asm operand {}", operand_idx));
+        expanded.push_str(&format_operand(asm.dialect, operand));
+    }
+    expanded
+}
+
+fn generate_asm_wrapper(name: &str, asm: &hir::InlineAsm, operands: &[AsmOperand]) -> String {
+    let mut generated = String::new();
+
+    // The same helper may be generated by multiple crates for a generic function.
+    writeln!(generated, ".pushsection .text").unwrap();
+    writeln!(generated, ".p2align 4").unwrap();
+    writeln!(generated, ".weak {}", name).unwrap();
+    writeln!(generated, ".hidden {}", name).unwrap();
+    writeln!(generated, ".type {}, @function", name).unwrap();
+    writeln!(generated, "{}:", name).unwrap();
+
+    // Prologue
+    writeln!(generated, "    push %rbp").unwrap();
+    writeln!(generated, "    mov %rsp, %rbp").unwrap();
+    for reg in CALLEE_SAVED.iter() {
+        writeln!(generated, "    push %{}", reg).unwrap();
+    }
+    writeln!(generated, "    push %rdi").unwrap();
+
+    // Load inputs
+    let load_ptr = scratch_reg(operands.iter().filter(|operand| operand.load));
+    writeln!(generated, "    mov %rdi, %{}", load_ptr).unwrap();
+    for (i, operand) in operands.iter().enumerate() {
+        if operand.load {
+            writeln!(
+                generated,
+                "    movq {}(%{}), %{}",
+                8 * i,
+                load_ptr,
+                GPRS[operand.reg.unwrap()][0]
+            )
+            .unwrap();
+        }
+    }
+
+    // Asm template
+    if let AsmDialect::Intel = asm.dialect {
+        writeln!(generated, ".intel_syntax noprefix").unwrap();
+    }
+    writeln!(generated, "{}", expand_template(asm, operands)).unwrap();
+    if let AsmDialect::Intel = asm.dialect {
+        writeln!(generated, ".att_syntax").unwrap();
+    }
+
+    // Store outputs
+    let store_ptr = scratch_reg(operands.iter().filter(|operand| operand.store));
+    writeln!(generated, "    movq {}(%rbp), %{}", BLOCK_PTR_OFFSET, store_ptr).unwrap();
+    for (i, operand) in operands.iter().enumerate() {
+        if operand.store {
+            writeln!(
+                generated,
+                "    movq %{}, {}(%{})",
+                GPRS[operand.reg.unwrap()][0],
+                8 * i,
+                store_ptr
+            )
+            .unwrap();
+        }
+    }
+
+    // Epilogue
+    writeln!(generated, "    lea {}(%rbp), %rsp", BLOCK_PTR_OFFSET + 8).unwrap();
+    for reg in CALLEE_SAVED.iter().rev() {
+        writeln!(generated, "    pop %{}", reg).unwrap();
+    }
+    writeln!(generated, "    pop %rbp").unwrap();
+    writeln!(generated, "    ret").unwrap();
+    writeln!(generated, ".size {}, .-{}", name, name).unwrap();
+    writeln!(generated, ".popsection").unwrap();
+    writeln!(generated).unwrap();
+
+    generated
+}
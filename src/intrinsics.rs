@@ -148,7 +148,7 @@ pub fn codegen_intrinsic_call<'a, 'tcx: 'a>(
         };
         size_of, <T> () {
             let size_of = fx.layout_of(T).size.bytes();
-            let size_of = CValue::const_val(fx, usize_layout.ty, size_of as i64);
+            let size_of = CValue::const_val(fx, usize_layout.ty, size_of.into());
             ret.write_cvalue(fx, size_of);
         };
         size_of_val, <T> (c ptr) {
@@ -170,7 +170,7 @@ pub fn codegen_intrinsic_call<'a, 'tcx: 'a>(
         };
         min_align_of, <T> () {
             let min_align = fx.layout_of(T).align.abi.bytes();
-            let min_align = CValue::const_val(fx, usize_layout.ty, min_align as i64);
+            let min_align = CValue::const_val(fx, usize_layout.ty, min_align.into());
             ret.write_cvalue(fx, min_align);
         };
         min_align_of_val, <T> (c ptr) {
@@ -191,7 +191,7 @@ pub fn codegen_intrinsic_call<'a, 'tcx: 'a>(
         };
         type_id, <T> () {
             let type_id = fx.tcx.type_id_hash(T);
-            let type_id = CValue::const_val(fx, u64_layout.ty, type_id as i64);
+            let type_id = CValue::const_val(fx, u64_layout.ty, type_id.into());
             ret.write_cvalue(fx, type_id);
         };
         _ if intrinsic.starts_with("unchecked_") || intrinsic == "exact_div", (c x, c y) {
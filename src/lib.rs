@@ -17,10 +17,11 @@ use std::any::Any;
 use std::fs::File;
 use std::sync::mpsc;
 
-use rustc::dep_graph::DepGraph;
+use rustc::dep_graph::{DepGraph, WorkProduct, WorkProductFileKind, WorkProductId};
+use rustc::mir::mono::{CodegenUnit, Linkage as RLinkage, Visibility};
 use rustc::middle::cstore::MetadataLoader;
 use rustc::session::{
-    config::{OutputFilenames, OutputType},
+    config::{DebugInfo, OutputFilenames, OutputType},
     CompileIncomplete,
 };
 use rustc::ty::query::Providers;
@@ -28,11 +29,18 @@ use rustc_codegen_ssa::back::linker::LinkerInfo;
 use rustc_codegen_ssa::CrateInfo;
 use rustc_codegen_utils::codegen_backend::CodegenBackend;
 use rustc_codegen_utils::link::out_filename;
+use rustc_mir::monomorphize::item::MonoItemExt;
+use rustc_mir::monomorphize::partitioning::CodegenUnitExt;
+use rustc_data_structures::stable_hasher::{HashStable, StableHasher, StableHasherResult};
+use rustc_data_structures::sync::Lock;
+use syntax::symbol::InternedString;
 
-use cranelift::codegen::settings;
+use cranelift::codegen::{isa, settings};
 use cranelift_faerie::*;
 
 use crate::constant::ConstantCx;
+use crate::emit_pool::{EmitJob, EmitPool, PendingFunction, SendModule};
+use crate::object_patch::ObjectPatches;
 use crate::prelude::*;
 
 mod abi;
@@ -40,13 +48,20 @@ mod allocator;
 mod analyze;
 mod archive;
 mod base;
+mod codegen_i128;
 mod common;
 mod constant;
+mod debuginfo;
+mod emit_pool;
+mod global_asm;
+mod inline_asm;
 mod intrinsics;
 mod link;
 mod link_copied;
+mod linkage;
 mod main_shim;
 mod metadata;
+mod object_patch;
 mod pretty_clif;
 mod trap;
 mod unimpl;
@@ -82,7 +97,8 @@ mod prelude {
     pub use rustc_codegen_ssa::{CodegenResults, CompiledModule, ModuleKind};
 
     pub use cranelift::codegen::ir::{
-        condcodes::IntCC, function::Function, ExternalName, FuncRef, Inst, StackSlot,
+        condcodes::IntCC, function::Function, ExternalName, FuncRef, Inst, SourceLoc, StackSlot,
+        ValueLabel,
     };
     pub use cranelift::codegen::isa::CallConv;
     pub use cranelift::codegen::Context;
@@ -93,6 +109,7 @@ mod prelude {
     pub use crate::abi::*;
     pub use crate::base::{trans_operand, trans_place};
     pub use crate::common::*;
+    pub use crate::debuginfo::{DebugContext, FunctionDebugContext};
     pub use crate::trap::*;
     pub use crate::unimpl::{unimpl, with_unimpl_span};
     pub use crate::Caches;
@@ -116,17 +133,6 @@ struct CraneliftCodegenBackend;
 
 impl CodegenBackend for CraneliftCodegenBackend {
     fn init(&self, sess: &Session) {
-        for cty in sess.opts.crate_types.iter() {
-            match *cty {
-                CrateType::Rlib | CrateType::Dylib | CrateType::Executable => {}
-                _ => {
-                    sess.err(&format!(
-                        "Rustc codegen cranelift doesn't support output type {}",
-                        cty
-                    ));
-                }
-            }
-        }
         match sess.lto() {
             Lto::Fat | Lto::Thin | Lto::ThinLocal => {
                 sess.warn("Rustc codegen cranelift doesn't support lto");
@@ -171,47 +177,20 @@ impl CodegenBackend for CraneliftCodegenBackend {
 
         let metadata = tcx.encode_metadata();
 
-        let mut flags_builder = settings::builder();
-        flags_builder.enable("is_pic").unwrap();
-        flags_builder.set("enable_verifier", if cfg!(debug_assertions) {
-            "true"
-        } else {
-            "false"
-        }).unwrap();
-
-        use rustc::session::config::OptLevel;
-        match tcx.sess.opts.optimize {
-            OptLevel::No => {
-                flags_builder.set("opt_level", "fastest").unwrap();
-            }
-            OptLevel::Less | OptLevel::Default => {}
-            OptLevel::Aggressive => {
-                flags_builder.set("opt_level", "best").unwrap();
-            }
-            OptLevel::Size | OptLevel::SizeMin => {
-                tcx.sess
-                    .warn("Optimizing for size is not supported. Just ignoring the request");
-            }
-        }
-
-        let flags = settings::Flags::new(flags_builder);
-        let isa =
-            cranelift::codegen::isa::lookup(tcx.sess.target.target.llvm_target.parse().unwrap())
-                .unwrap()
-                .finish(flags);
-
-        // TODO: move to the end of this function when compiling libcore doesn't have unimplemented stuff anymore
-        save_incremental(tcx);
-        tcx.sess.warn("Saved incremental data");
-
-        let mut log = if cfg!(debug_assertions) {
+        let log = Lock::new(if cfg!(debug_assertions) {
             Some(File::create(concat!(env!("CARGO_MANIFEST_DIR"), "/target/out/log.txt")).unwrap())
         } else {
             None
-        };
+        });
 
         if std::env::var("SHOULD_RUN").is_ok() {
-            let mut jit_module: Module<SimpleJITBackend> = Module::new(SimpleJITBuilder::new());
+            let mut jit_builder =
+                SimpleJITBuilder::new(cranelift_module::default_libcall_names());
+            jit_builder.symbol(
+                "__cg_clif_jit_tls_get_addr",
+                crate::constant::jit_tls_get_addr as *const u8,
+            );
+            let mut jit_module: Module<SimpleJITBackend> = Module::new(jit_builder);
             assert_eq!(pointer_ty(tcx), jit_module.target_config().pointer_type());
 
             let sig = Signature {
@@ -228,7 +207,38 @@ impl CodegenBackend for CraneliftCodegenBackend {
                 .declare_function("main", Linkage::Import, &sig)
                 .unwrap();
 
-            codegen_mono_items(tcx, &mut jit_module, &mut log);
+            // Everything is put in a single module, so items which are part of multiple codegen
+            // units must only be codegened once.
+            let (_, cgus) = tcx.collect_and_partition_mono_items(LOCAL_CRATE);
+            let mut cgus = cgus.iter().collect::<Vec<_>>();
+            cgus.sort_by_key(|cgu| cgu.name().as_str());
+            let mut seen_mono_items = FxHashSet::default();
+            let mono_items = cgus
+                .iter()
+                .map(|cgu| cgu.items_in_deterministic_order(tcx))
+                .flatten()
+                .filter(|&(mono_item, _)| seen_mono_items.insert(mono_item))
+                .collect::<Vec<_>>();
+
+            if mono_items.iter().any(|&(mono_item, _)| match mono_item {
+                MonoItem::GlobalAsm(_) => true,
+                _ => false,
+            }) {
+                tcx.sess.fatal("global_asm! is not supported in JIT mode");
+            }
+
+            let (global_asm, object_patches) =
+                codegen_mono_items(tcx, &mut jit_module, None, None, mono_items, &log);
+            if !global_asm.is_empty() {
+                tcx.sess.fatal("Inline asm is not supported in JIT mode");
+            }
+            if !object_patches.alignments.is_empty() {
+                tcx.sess
+                    .fatal("Alignments bigger than 128 bytes are not supported in JIT mode");
+            }
+            crate::main_shim::maybe_create_entry_wrapper(tcx, &mut jit_module);
+            codegen_allocator(tcx, &mut jit_module);
+            jit_module.finalize_definitions();
 
             tcx.sess.abort_if_errors();
             println!("Compiled everything");
@@ -245,42 +255,123 @@ impl CodegenBackend for CraneliftCodegenBackend {
             jit_module.finish();
             ::std::process::exit(0);
         } else {
-            let mut faerie_module: Module<FaerieBackend> = Module::new(
-                FaerieBuilder::new(
-                    isa,
-                    "some_file.o".to_string(),
-                    FaerieTrapCollection::Disabled,
-                    FaerieBuilder::default_libcall_names(),
-                )
-                .unwrap(),
-            );
-            assert_eq!(
-                pointer_ty(tcx),
-                faerie_module.target_config().pointer_type()
+            let (_, cgus) = tcx.collect_and_partition_mono_items(LOCAL_CRATE);
+
+            let before = ::std::time::Instant::now();
+            println!("[codegen mono items] start");
+
+            // The codegen units are translated on this thread, while the pool compiles and writes
+            // the objects of the previous ones. Each codegen unit is one job, so more workers than
+            // codegen units would never be busy.
+            let num_workers = tcx
+                .sess
+                .opts
+                .debugging_opts
+                .query_threads
+                .unwrap_or_else(|| tcx.sess.codegen_units())
+                .min(cgus.len());
+            let emit_pool = EmitPool::new(num_workers);
+
+            let mut modules = Vec::new();
+            let mut work_products = FxHashMap::default();
+            let mut new_modules = Vec::new();
+            let mut reused_cgus = 0;
+            for cgu in cgus.iter() {
+                let cgu_name = cgu.name().as_str().to_string();
+                if let CguReuse::Yes = determine_cgu_reuse(tcx, cgu) {
+                    for (module, work_product) in reuse_cgu_modules(tcx, &cgu_name) {
+                        modules.push(module);
+                        work_products.extend(work_product);
+                    }
+                    reused_cgus += 1;
+                    continue;
+                }
+
+                let dep_node = cgu.codegen_dep_node(tcx);
+                let (ModuleCodegenResult(cgu_modules), _) = tcx.dep_graph.with_task(
+                    dep_node,
+                    tcx,
+                    (cgu.name().clone(), &log, &emit_pool),
+                    module_codegen,
+                );
+                new_modules.extend(cgu_modules);
+            }
+            if tcx.sess.opts.debugging_opts.incremental_info {
+                println!(
+                    "[incremental] Re-using {} out of {} codegen units",
+                    reused_cgus,
+                    cgus.len()
+                );
+            }
+
+            let crate_name = tcx.crate_name(LOCAL_CRATE);
+
+            let metadata_name = format!("{}.metadata", crate_name);
+            let mut metadata_module = new_module(tcx, metadata_name.clone());
+            let mut metadata_object_patches = ObjectPatches::default();
+            crate::metadata::write_metadata(
+                tcx,
+                &mut metadata_module,
+                &mut metadata_object_patches,
+                &metadata,
             );
+            modules.push(emit_module(
+                tcx,
+                &emit_pool,
+                metadata_name,
+                ModuleKind::Regular,
+                metadata_module,
+                vec![],
+                metadata_object_patches,
+                None,
+            ));
+
+            let allocator_name = format!("{}.allocator", crate_name);
+            let mut allocator_module = new_module(tcx, allocator_name.clone());
+            let allocator_module = if codegen_allocator(tcx, &mut allocator_module) {
+                Some(emit_module(
+                    tcx,
+                    &emit_pool,
+                    allocator_name,
+                    ModuleKind::Allocator,
+                    allocator_module,
+                    vec![],
+                    ObjectPatches::default(),
+                    None,
+                ))
+            } else {
+                None
+            };
 
-            codegen_mono_items(tcx, &mut faerie_module, &mut log);
+            emit_pool.join(tcx.sess);
+
+            let after = ::std::time::Instant::now();
+            println!("[codegen mono items] end time: {:?}", after - before);
+
+            // The objects have to be written before they can be copied to the incremental cache.
+            for module in new_modules {
+                work_products.extend(
+                    rustc_incremental::copy_cgu_workproducts_to_incr_comp_cache_dir(
+                        tcx.sess,
+                        &module.name,
+                        &[(WorkProductFileKind::Object, module.object.clone().unwrap())],
+                    ),
+                );
+                modules.push(module);
+            }
+            // The objects are linked in this order, which determines the order of the global asm
+            // of the codegen units in the output.
+            modules.sort_by(|a, b| a.name.cmp(&b.name));
 
             tcx.sess.abort_if_errors();
 
-            let artifact = faerie_module.finish().artifact;
-
-            let tmp_file = tcx
-                .output_filenames(LOCAL_CRATE)
-                .temp_path(OutputType::Object, None);
-            let obj = artifact.emit().unwrap();
-            std::fs::write(&tmp_file, obj).unwrap();
+            rustc_incremental::assert_dep_graph(tcx);
+            rustc_incremental::save_dep_graph(tcx);
 
-            return Box::new(CodegenResults {
-                crate_name: tcx.crate_name(LOCAL_CRATE),
-                modules: vec![CompiledModule {
-                    name: "dummy_name".to_string(),
-                    kind: ModuleKind::Regular,
-                    object: Some(tmp_file),
-                    bytecode: None,
-                    bytecode_compressed: None,
-                }],
-                allocator_module: None,
+            return Box::new((CodegenResults {
+                crate_name,
+                modules,
+                allocator_module,
                 metadata_module: CompiledModule {
                     name: "dummy_metadata".to_string(),
                     kind: ModuleKind::Metadata,
@@ -293,7 +384,7 @@ impl CodegenBackend for CraneliftCodegenBackend {
                 windows_subsystem: None, // Windows is not yet supported
                 linker_info: LinkerInfo::new(tcx),
                 crate_info: CrateInfo::new(tcx),
-            });
+            }, work_products));
         }
     }
 
@@ -301,51 +392,174 @@ impl CodegenBackend for CraneliftCodegenBackend {
         &self,
         res: Box<dyn Any>,
         sess: &Session,
-        _dep_graph: &DepGraph,
+        dep_graph: &DepGraph,
         outputs: &OutputFilenames,
     ) -> Result<(), CompileIncomplete> {
-        let res = *res
-            .downcast::<CodegenResults>()
+        let (res, work_products) = *res
+            .downcast::<(CodegenResults, FxHashMap<WorkProductId, WorkProduct>)>()
             .expect("Expected CraneliftCodegenBackend's CodegenResult, found Box<Any>");
 
+        rustc_incremental::save_work_product_index(sess, dep_graph, work_products);
+
         for &crate_type in sess.opts.crate_types.iter() {
             let output_name = out_filename(sess, crate_type, &outputs, &res.crate_name.as_str());
             match crate_type {
                 CrateType::Rlib => link::link_rlib(sess, &res, output_name),
-                CrateType::Executable => link::link_bin(sess, &res, &output_name),
-                _ => sess.fatal(&format!("Unsupported crate type: {:?}", crate_type)),
+                CrateType::Staticlib => link_copied::link_staticlib(sess, &res, &output_name),
+                CrateType::Executable
+                | CrateType::Dylib
+                | CrateType::Cdylib
+                | CrateType::ProcMacro => {
+                    link::link_natively(sess, crate_type, &res, &output_name)
+                }
             }
         }
+
+        rustc_incremental::finalize_session_directory(sess, res.crate_hash);
+
         Ok(())
     }
 }
 
+fn build_isa(sess: &Session) -> Box<dyn isa::TargetIsa + 'static> {
+    let mut flags_builder = settings::builder();
+    flags_builder.enable("is_pic").unwrap();
+    flags_builder.set("enable_verifier", if cfg!(debug_assertions) {
+        "true"
+    } else {
+        "false"
+    }).unwrap();
+
+    use rustc::session::config::OptLevel;
+    match sess.opts.optimize {
+        OptLevel::No => {
+            flags_builder.set("opt_level", "none").unwrap();
+        }
+        OptLevel::Less | OptLevel::Default => {
+            flags_builder.set("opt_level", "speed").unwrap();
+        }
+        OptLevel::Aggressive => {
+            flags_builder.set("opt_level", "speed_and_size").unwrap();
+        }
+        OptLevel::Size | OptLevel::SizeMin => {
+            sess.warn("Optimizing for size is not supported. Just ignoring the request");
+        }
+    }
+
+    let flags = settings::Flags::new(flags_builder);
+    cranelift::codegen::isa::lookup(sess.target.target.llvm_target.parse().unwrap())
+        .unwrap()
+        .finish(flags)
+}
+
+fn new_module<'a, 'tcx: 'a>(tcx: TyCtxt<'a, 'tcx, 'tcx>, name: String) -> Module<FaerieBackend> {
+    let module: Module<FaerieBackend> = Module::new(
+        FaerieBuilder::new(
+            build_isa(tcx.sess),
+            name + ".o",
+            FaerieTrapCollection::Disabled,
+            cranelift_module::default_libcall_names(),
+        )
+        .unwrap(),
+    );
+    assert_eq!(pointer_ty(tcx), module.target_config().pointer_type());
+    module
+}
+
+fn emit_module<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    emit_pool: &EmitPool,
+    name: String,
+    kind: ModuleKind,
+    module: Module<FaerieBackend>,
+    pending_functions: Vec<PendingFunction>,
+    object_patches: ObjectPatches,
+    debug: Option<DebugContext>,
+) -> CompiledModule {
+    let obj_path = tcx
+        .output_filenames(LOCAL_CRATE)
+        .temp_path(OutputType::Object, Some(&name));
+    emit_pool.submit(EmitJob::Module {
+        module: SendModule(module),
+        pending_functions,
+        object_patches,
+        debug: debug.map(DebugContext::finish),
+        obj_path: obj_path.clone(),
+    });
+
+    CompiledModule {
+        name,
+        kind,
+        object: Some(obj_path),
+        bytecode: None,
+        bytecode_compressed: None,
+    }
+}
+
 fn codegen_mono_items<'a, 'tcx: 'a>(
     tcx: TyCtxt<'a, 'tcx, 'tcx>,
     module: &mut Module<impl Backend + 'static>,
-    log: &mut Option<File>,
-) {
+    mut debug_context: Option<&mut DebugContext<'tcx>>,
+    mut pending_functions: Option<&mut Vec<PendingFunction>>,
+    mono_items: impl IntoIterator<Item = (MonoItem<'tcx>, (RLinkage, Visibility))>,
+    log: &Lock<Option<File>>,
+) -> (String, ObjectPatches) {
     let mut caches = Caches::new();
+    let mut global_asm = String::new();
     let mut ccx = ConstantCx::default();
+    let mut object_patches = ObjectPatches::default();
 
-    let (_, cgus) = tcx.collect_and_partition_mono_items(LOCAL_CRATE);
-    let mono_items = cgus
-        .iter()
-        .map(|cgu| cgu.items().iter())
-        .flatten()
-        .collect::<FxHashSet<(_, _)>>();
-
-    let before = ::std::time::Instant::now();
-    println!("[codegen mono items] start");
-
-    for (&mono_item, &(_linkage, _vis)) in mono_items {
-        unimpl::try_unimpl(tcx, log, || {
-            base::trans_mono_item(tcx, module, &mut caches, &mut ccx, mono_item);
-        });
+    for (mono_item, (linkage, visibility)) in mono_items {
+        match mono_item {
+            MonoItem::GlobalAsm(node_id) => {
+                crate::global_asm::codegen_global_asm(tcx, &mut global_asm, node_id);
+            }
+            _ => {
+                unimpl::try_unimpl(tcx, log, || {
+                    let (linkage, hidden) =
+                        crate::linkage::get_clif_linkage(tcx, mono_item, linkage, visibility);
+                    if hidden {
+                        object_patches
+                            .hidden
+                            .insert(mono_item.symbol_name(tcx).as_str().to_string());
+                    }
+                    if let MonoItem::Fn(instance) = mono_item {
+                        // Faerie puts every function in its own section, which can be renamed.
+                        if let Some(link_section) = tcx.codegen_fn_attrs(instance.def_id()).link_section {
+                            object_patches.link_sections.insert(
+                                mono_item.symbol_name(tcx).as_str().to_string(),
+                                link_section.as_str().to_string(),
+                            );
+                        }
+                    }
+                    base::trans_mono_item(
+                        tcx,
+                        module,
+                        &mut caches,
+                        &mut ccx,
+                        debug_context.as_mut().map(|debug_context| &mut **debug_context),
+                        pending_functions
+                            .as_mut()
+                            .map(|pending_functions| &mut **pending_functions),
+                        &mut global_asm,
+                        mono_item,
+                        linkage,
+                    );
+                });
+            }
+        }
     }
 
-    crate::main_shim::maybe_create_entry_wrapper(tcx, module);
+    ccx.finalize(tcx, module, &mut object_patches);
+
+    (global_asm, object_patches)
+}
 
+/// Returns whether an allocator shim was needed.
+fn codegen_allocator<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    module: &mut Module<impl Backend + 'static>,
+) -> bool {
     let any_dynamic_crate = tcx
         .sess
         .dependency_formats
@@ -355,22 +569,163 @@ fn codegen_mono_items<'a, 'tcx: 'a>(
             use rustc::middle::dependency_format::Linkage;
             list.iter().any(|&linkage| linkage == Linkage::Dynamic)
         });
+    // When linking against a dylib, the allocator shim is provided by the dylib.
     if any_dynamic_crate {
+        false
     } else if let Some(kind) = *tcx.sess.allocator_kind.get() {
         allocator::codegen(module, kind);
+        true
+    } else {
+        false
+    }
+}
+
+enum CguReuse {
+    No,
+    Yes,
+}
+
+fn determine_cgu_reuse<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    cgu: &CodegenUnit<'tcx>,
+) -> CguReuse {
+    if !tcx.dep_graph.is_fully_enabled() {
+        return CguReuse::No;
+    }
+
+    let work_product_id = &cgu.work_product_id();
+    if tcx
+        .dep_graph
+        .previous_work_product(work_product_id)
+        .is_none()
+    {
+        // We don't have anything cached for this CGU. This can happen
+        // if the CGU did not exist in the previous session.
+        return CguReuse::No;
+    }
+
+    // Try to mark the CGU as green. If it we can do so, it means that nothing
+    // affecting the codegen unit has changed and we can re-use a cached version.
+    let dep_node = cgu.codegen_dep_node(tcx);
+    assert!(
+        !tcx.dep_graph.dep_node_exists(&dep_node),
+        "CompileCodegenUnit dep-node for CGU `{}` already exists before marking.",
+        cgu.name()
+    );
+
+    if tcx.dep_graph.try_mark_green(tcx, &dep_node).is_some() {
+        CguReuse::Yes
+    } else {
+        CguReuse::No
+    }
+}
+
+/// Copy the objects of a green codegen unit from the incremental cache. The global asm object
+/// is saved as separate work product, as it is only present when the codegen unit contains asm.
+fn reuse_cgu_modules<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    cgu_name: &str,
+) -> Vec<(CompiledModule, Option<(WorkProductId, WorkProduct)>)> {
+    let incr_comp_session_dir = tcx.sess.incr_comp_session_dir();
+    let mut modules = Vec::new();
+
+    for name in &[cgu_name.to_string(), format!("{}.asm", cgu_name)] {
+        let work_product_id = WorkProductId::from_cgu_name(name);
+        let work_product = match tcx.dep_graph.previous_work_product(&work_product_id) {
+            Some(work_product) => work_product,
+            None => continue,
+        };
+
+        let obj_out = tcx
+            .output_filenames(LOCAL_CRATE)
+            .temp_path(OutputType::Object, Some(name));
+        for (kind, saved_file) in &work_product.saved_files {
+            assert_eq!(*kind, WorkProductFileKind::Object);
+            let source_file = rustc_incremental::in_incr_comp_dir(&incr_comp_session_dir, saved_file);
+            if let Err(err) = rustc_fs_util::link_or_copy(&source_file, &obj_out) {
+                tcx.sess.err(&format!(
+                    "unable to copy {} to {}: {}",
+                    source_file.display(),
+                    obj_out.display(),
+                    err
+                ));
+            }
+        }
+
+        modules.push((
+            CompiledModule {
+                name: name.clone(),
+                kind: ModuleKind::Regular,
+                object: Some(obj_out),
+                bytecode: None,
+                bytecode_compressed: None,
+            },
+            Some((work_product_id, work_product)),
+        ));
     }
 
-    ccx.finalize(tcx, module);
-    module.finalize_definitions();
+    modules
+}
+
+/// The objects of a codegen unit. They are only used by the codegen unit itself, so they don't
+/// need to be hashed when recording the dep node of it.
+struct ModuleCodegenResult(Vec<CompiledModule>);
 
-    let after = ::std::time::Instant::now();
-    println!("[codegen mono items] end time: {:?}", after - before);
+impl<HCX> HashStable<HCX> for ModuleCodegenResult {
+    fn hash_stable<W: StableHasherResult>(&self, _: &mut HCX, _: &mut StableHasher<W>) {
+        // do nothing
+    }
 }
 
-fn save_incremental<'a, 'tcx>(tcx: TyCtxt<'a, 'tcx, 'tcx>) {
-    rustc_incremental::assert_dep_graph(tcx);
-    rustc_incremental::save_dep_graph(tcx);
-    rustc_incremental::finalize_session_directory(tcx.sess, tcx.crate_hash(LOCAL_CRATE));
+fn module_codegen<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    (cgu_name, log, emit_pool): (InternedString, &Lock<Option<File>>, &EmitPool),
+) -> ModuleCodegenResult {
+    let cgu = tcx.codegen_unit(cgu_name);
+    let cgu_name = cgu.name().as_str().to_string();
+
+    let mut module = new_module(tcx, cgu_name.clone());
+
+    let mut debug = if tcx.sess.opts.debuginfo != DebugInfo::None {
+        let address_size = module.target_config().pointer_type().bytes() as u8;
+        Some(DebugContext::new(tcx, address_size))
+    } else {
+        None
+    };
+
+    // The order of the mono items determines the order of the global asm, so it has to be
+    // deterministic.
+    let mono_items = cgu.items_in_deterministic_order(tcx);
+    let mut pending_functions = Vec::new();
+    let (global_asm, object_patches) = codegen_mono_items(
+        tcx,
+        &mut module,
+        debug.as_mut(),
+        Some(&mut pending_functions),
+        mono_items,
+        log,
+    );
+    if crate::main_shim::contains_entry_fn(tcx, &cgu) {
+        crate::main_shim::maybe_create_entry_wrapper(tcx, &mut module);
+    }
+
+    let mut modules = vec![emit_module(
+        tcx,
+        emit_pool,
+        cgu_name.clone(),
+        ModuleKind::Regular,
+        module,
+        pending_functions,
+        object_patches,
+        debug,
+    )];
+    modules.extend(crate::global_asm::compile_global_asm(
+        tcx,
+        emit_pool,
+        &cgu_name,
+        global_asm,
+    ));
+    ModuleCodegenResult(modules)
 }
 
 /// This is the entrypoint for a hot plugged rustc_codegen_cranelift
@@ -1,11 +1,10 @@
 use std::env;
-use std::fs::File;
 use std::io;
 use std::path::{Path, PathBuf};
 
 use tempfile::Builder as TempFileBuilder;
 
-use rustc::session::config::{self, CrateType, DebugInfo, RUST_CGU_EXT};
+use rustc::session::config::{self, CrateType, DebugInfo};
 use rustc::session::search_paths::PathKind;
 use rustc::session::Session;
 use rustc_codegen_ssa::back::command::Command;
@@ -19,41 +18,28 @@ use crate::prelude::*;
 use crate::link_copied::*;
 
 pub(crate) fn link_rlib(sess: &Session, res: &CodegenResults, output_name: PathBuf) {
-    let file = File::create(&output_name).unwrap();
-    let mut builder = ar::Builder::new(file);
+    let mut members = Vec::new();
 
     // Add main object file
     for module in &res.modules {
         if let Some(ref object_path) = module.object {
-            let object = File::open(object_path).expect("Someone deleted our object file");
-            let object_len = object.metadata().unwrap().len();
-            builder
-                .append(
-                    &ar::Header::new(
-                        (module.name.to_string() + RUST_CGU_EXT).into_bytes(),
-                        object_len,
-                    ),
-                    object,
-                )
-                .unwrap();
+            let name = object_path.file_name().unwrap().to_str().unwrap().to_string();
+            let object = std::fs::read(object_path).expect("Someone deleted our object file");
+            members.push((name, object));
         }
     }
 
     // Non object files need to be added after object files, because ranlib will
     // try to read the native architecture from the first file, even if it isn't
     // an object file
-    builder
-        .append(
-            &ar::Header::new(
-                crate::metadata::METADATA_FILENAME.as_bytes().to_vec(),
-                res.metadata.raw_data.len() as u64,
-            ),
-            ::std::io::Cursor::new(res.metadata.raw_data.clone()),
-        )
-        .unwrap();
-
-    // Finalize archive
-    std::mem::drop(builder);
+    members.push((
+        crate::metadata::METADATA_FILENAME.to_string(),
+        res.metadata.raw_data.clone(),
+    ));
+
+    if let Err(err) = crate::archive::write_gnu_archive(&output_name, &members) {
+        sess.fatal(&format!("Couldn't write rlib: {}", err));
+    }
 
     // Run ranlib to be able to link the archive
     let status = std::process::Command::new("ranlib")
@@ -65,7 +51,12 @@ pub(crate) fn link_rlib(sess: &Session, res: &CodegenResults, output_name: PathB
     }
 }
 
-pub(crate) fn link_bin(sess: &Session, codegen_results: &CodegenResults, out_filename: &Path) {
+pub(crate) fn link_natively(
+    sess: &Session,
+    crate_type: CrateType,
+    codegen_results: &CodegenResults,
+    out_filename: &Path,
+) {
     let tmpdir = match TempFileBuilder::new().prefix("rustc").tempdir() {
         Ok(tmpdir) => tmpdir,
         Err(err) => sess.fatal(&format!("couldn't create a temp dir: {}", err)),
@@ -88,11 +79,16 @@ pub(crate) fn link_bin(sess: &Session, codegen_results: &CodegenResults, out_fil
     }
     cmd.args(&sess.opts.debugging_opts.pre_link_arg);
 
-    for obj in &sess.target.target.options.pre_link_objects_exe {
+    let pre_link_objects = if crate_type == CrateType::Executable {
+        &sess.target.target.options.pre_link_objects_exe
+    } else {
+        &sess.target.target.options.pre_link_objects_dll
+    };
+    for obj in pre_link_objects {
         cmd.arg(root.join(obj));
     }
 
-    if sess.crt_static() {
+    if crate_type == CrateType::Executable && sess.crt_static() {
         for obj in &sess.target.target.options.pre_link_objects_exe_crt {
             cmd.arg(root.join(obj));
         }
@@ -110,7 +106,7 @@ pub(crate) fn link_bin(sess: &Session, codegen_results: &CodegenResults, out_fil
     {
         let target_cpu = "x86_64-apple-darwin"; //::llvm_util::target_cpu(sess);
         let mut linker = codegen_results.linker_info.to_linker(cmd, &sess, flavor, target_cpu);
-        link_args(&mut *linker, flavor, sess, CrateType::Executable, tmpdir.path(),
+        link_args(&mut *linker, flavor, sess, crate_type, tmpdir.path(),
                   out_filename, codegen_results);
         cmd = linker.finalize();
     }
@@ -299,7 +295,8 @@ fn link_args(cmd: &mut dyn Linker,
     // Try to strip as much out of the generated object by removing unused
     // sections if possible. See more comments in linker.rs
     if !sess.opts.cg.link_dead_code {
-        let keep_metadata = crate_type == config::CrateType::Dylib;
+        let keep_metadata = crate_type == config::CrateType::Dylib
+            || crate_type == config::CrateType::ProcMacro;
         cmd.gc_sections(keep_metadata);
     }
 
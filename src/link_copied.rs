@@ -9,15 +9,16 @@ use std::process::{Output, Stdio};
 
 use log::info;
 
-use rustc::middle::cstore::{NativeLibrary, NativeLibraryKind};
+use rustc::middle::cstore::{LibSource, NativeLibrary, NativeLibraryKind};
 use rustc::middle::dependency_format::Linkage;
-use rustc::session::config::{self, OutputType, RUST_CGU_EXT};
+use rustc::session::config::{self, OutputType, PrintRequest, RUST_CGU_EXT};
 use rustc::session::search_paths::PathKind;
 use rustc::session::Session;
 use rustc::util::common::time;
 use rustc_codegen_ssa::back::command::Command;
 use rustc_codegen_ssa::back::linker::*;
 use rustc_codegen_ssa::back::link::*;
+use rustc_codegen_ssa::CrateInfo;
 use rustc_data_structures::fx::FxHashSet;
 use rustc_fs_util::fix_windows_verbatim_for_gcc;
 use syntax::attr;
@@ -29,7 +30,7 @@ use crate::metadata::METADATA_FILENAME;
 
 
 // cg_clif doesn't have bytecode, so this is just a dummy
-const RLIB_BYTECODE_EXTENSION: &str = ".cg_clif_bytecode_dummy";
+pub(crate) const RLIB_BYTECODE_EXTENSION: &str = ".cg_clif_bytecode_dummy";
 
 fn archive_search_paths(sess: &Session) -> Vec<PathBuf> {
     let mut search = Vec::new();
@@ -50,6 +51,162 @@ fn archive_config<'a>(sess: &'a Session,
     }
 }
 
+// Create a static archive
+//
+// This is essentially the same thing as an rlib, but it also involves adding
+// all of the upstream crates' objects into the archive. This will slurp in
+// all of the native libraries of upstream dependencies as well.
+//
+// Additionally, there's no way for us to link dynamic libraries, so we warn
+// about all dynamic library dependencies that they're not linked in.
+//
+// There's no need to include metadata in a static archive, so ensure to not
+// link in the metadata object file (and also don't prepare the archive with a
+// metadata file).
+pub fn link_staticlib(sess: &Session, codegen_results: &CodegenResults, out_filename: &Path) {
+    let mut ab = ArchiveBuilder::new(archive_config(sess, out_filename, None));
+
+    for module in codegen_results.modules.iter().chain(&codegen_results.allocator_module) {
+        if let Some(ref object_path) = module.object {
+            ab.add_file(object_path);
+        }
+    }
+    for lib in codegen_results.crate_info.used_libraries.iter() {
+        match lib.kind {
+            NativeLibraryKind::NativeStatic => {}
+            NativeLibraryKind::NativeStaticNobundle |
+            NativeLibraryKind::NativeFramework |
+            NativeLibraryKind::NativeUnknown => continue,
+        }
+        if let Some(name) = lib.name {
+            if relevant_lib(sess, lib) {
+                ab.add_native_library(&name.as_str());
+            }
+        }
+    }
+
+    let mut all_native_libs = vec![];
+
+    let res = each_linked_rlib(sess, &codegen_results.crate_info, &mut |cnum, path| {
+        let name = &codegen_results.crate_info.crate_name[&cnum];
+        let native_libs = &codegen_results.crate_info.native_libraries[&cnum];
+
+        // Here when we include the rlib into our staticlib we need to make a
+        // decision whether to include the extra object files along the way.
+        // These extra object files come from statically included native
+        // libraries, but they may be cfg'd away with #[link(cfg(..))].
+        //
+        // This unstable feature, though, only needs liblibc to work. The only
+        // use case there is where musl is statically included in liblibc.rlib,
+        // so if we don't want the included version we just need to skip it. As
+        // a result the logic here is that if *any* linked library is cfg'd away
+        // we just skip all object files.
+        //
+        // Clearly this is not sufficient for a general purpose feature, and
+        // we'd want to read from the library's metadata to determine which
+        // object files come from where and selectively skip them.
+        let skip_object_files = native_libs.iter().any(|lib| {
+            lib.kind == NativeLibraryKind::NativeStatic && !relevant_lib(sess, lib)
+        });
+        ab.add_rlib(path,
+                    &name.as_str(),
+                    are_upstream_rust_objects_already_included(sess) &&
+                        !ignored_for_lto(sess, &codegen_results.crate_info, cnum),
+                    skip_object_files).unwrap();
+
+        // cg_clif doesn't bundle native static libraries into rlibs, so add them here.
+        for lib in native_libs.iter() {
+            if let (NativeLibraryKind::NativeStatic, Some(name)) = (lib.kind, lib.name) {
+                if relevant_lib(sess, lib) {
+                    ab.add_native_library(&name.as_str());
+                }
+            }
+        }
+
+        all_native_libs.extend(native_libs.iter().cloned());
+    });
+    if let Err(e) = res {
+        sess.fatal(&e);
+    }
+
+    ab.update_symbols();
+    ab.build();
+
+    if !all_native_libs.is_empty() {
+        if sess.opts.prints.contains(&PrintRequest::NativeStaticLibs) {
+            print_native_static_libs(sess, &all_native_libs);
+        }
+    }
+}
+
+fn print_native_static_libs(sess: &Session, all_native_libs: &[NativeLibrary]) {
+    let lib_args: Vec<_> = all_native_libs.iter()
+        .filter(|l| relevant_lib(sess, l))
+        .filter_map(|lib| {
+            let name = lib.name?;
+            match lib.kind {
+                NativeLibraryKind::NativeStaticNobundle |
+                NativeLibraryKind::NativeUnknown => {
+                    if sess.target.target.options.is_like_msvc {
+                        Some(format!("{}.lib", name))
+                    } else {
+                        Some(format!("-l{}", name))
+                    }
+                },
+                NativeLibraryKind::NativeFramework => {
+                    // ld-only syntax, since there are no frameworks in MSVC
+                    Some(format!("-framework {}", name))
+                },
+                // These are included, no need to print them
+                NativeLibraryKind::NativeStatic => None,
+            }
+        })
+        .collect();
+    if !lib_args.is_empty() {
+        sess.note_without_error("Link against the following native artifacts when linking \
+                                 against this static library. The order and any duplication \
+                                 can be significant on some platforms.");
+        // Prefix for greppability
+        sess.note_without_error(&format!("native-static-libs: {}", &lib_args.join(" ")));
+    }
+}
+
+fn each_linked_rlib(sess: &Session,
+                    info: &CrateInfo,
+                    f: &mut dyn FnMut(CrateNum, &Path)) -> Result<(), String> {
+    let crates = info.used_crates_static.iter();
+    let fmts = sess.dependency_formats.borrow();
+    let fmts = fmts.get(&config::CrateType::Executable)
+                   .or_else(|| fmts.get(&config::CrateType::Staticlib))
+                   .or_else(|| fmts.get(&config::CrateType::Cdylib))
+                   .or_else(|| fmts.get(&config::CrateType::ProcMacro));
+    let fmts = match fmts {
+        Some(f) => f,
+        None => return Err("could not find formats for rlibs".to_string())
+    };
+    for &(cnum, ref path) in crates {
+        match fmts.get(cnum.as_usize() - 1) {
+            Some(&Linkage::NotLinked) |
+            Some(&Linkage::IncludedFromDylib) => continue,
+            Some(_) => {}
+            None => return Err("could not find formats for rlibs".to_string())
+        }
+        let name = &info.crate_name[&cnum];
+        let path = match *path {
+            LibSource::Some(ref p) => p,
+            LibSource::MetadataOnly => {
+                return Err(format!("could not find rlib for: `{}`, found rmeta (metadata) file",
+                                   name));
+            }
+            LibSource::None => {
+                return Err(format!("could not find rlib for: `{}`", name));
+            }
+        };
+        f(cnum, &path);
+    }
+    Ok(())
+}
+
 pub fn exec_linker(sess: &Session, cmd: &mut Command, out_filename: &Path, tmpdir: &Path)
     -> io::Result<Output>
 {
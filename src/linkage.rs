@@ -0,0 +1,42 @@
+use rustc::mir::mono::{Linkage as RLinkage, Visibility};
+
+use crate::prelude::*;
+
+/// Returns the linkage of the mono item and whether its symbol needs to be hidden. Cranelift has
+/// no hidden linkage, so hidden visibility is applied by `crate::object_patch`.
+pub fn get_clif_linkage<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    mono_item: MonoItem<'tcx>,
+    linkage: RLinkage,
+    visibility: Visibility,
+) -> (Linkage, bool) {
+    let clif_linkage = match linkage {
+        RLinkage::External => Linkage::Export,
+        RLinkage::Internal | RLinkage::Private => Linkage::Local,
+        // Multiple crates may contain a definition of these. The linker picks one of them.
+        RLinkage::WeakAny
+        | RLinkage::WeakODR
+        | RLinkage::LinkOnceAny
+        | RLinkage::LinkOnceODR => Linkage::Preemptible,
+        _ => unimpl!(
+            "Unsupported linkage {:?} with visibility {:?} for {:?}",
+            linkage,
+            visibility,
+            mono_item
+        ),
+    };
+
+    match mono_item {
+        // The TLS accessor is assembled into a separate object file, which can't refer to local
+        // symbols.
+        MonoItem::Static(def_id)
+            if clif_linkage == Linkage::Local && crate::constant::is_thread_local(tcx, def_id) =>
+        {
+            (Linkage::Export, true)
+        }
+        _ => (
+            clif_linkage,
+            clif_linkage != Linkage::Local && visibility == Visibility::Hidden,
+        ),
+    }
+}
@@ -1,5 +1,22 @@
+use rustc::mir::mono::CodegenUnit;
+
 use crate::prelude::*;
 
+/// Whether the given codegen unit contains the users main function. The entry wrapper has to be
+/// put in the same codegen unit, as the main function may be internal to it.
+pub fn contains_entry_fn<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    cgu: &CodegenUnit<'tcx>,
+) -> bool {
+    match *tcx.sess.entry_fn.borrow() {
+        Some((id, _, _)) => {
+            let main_instance = Instance::mono(tcx, tcx.hir.local_def_id(id));
+            cgu.items().contains_key(&MonoItem::Fn(main_instance))
+        }
+        None => false,
+    }
+}
+
 /// Create the `main` function which will initialize the rust runtime and call
 /// users main function.
 pub fn maybe_create_entry_wrapper<'a, 'tcx: 'a>(
@@ -1,9 +1,17 @@
-use rustc::middle::cstore::MetadataLoader;
+use rustc::middle::cstore::{EncodedMetadata, MetadataLoader};
+use rustc::session::config::CrateType;
 use rustc_data_structures::owning_ref::{self, OwningRef};
 use rustc_data_structures::rustc_erase_owner;
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use crate::object_patch::ObjectPatches;
+use crate::prelude::*;
+
 pub const METADATA_FILENAME: &str = "rust.metadata.bin";
 
 pub struct CraneliftMetadataLoader;
@@ -32,9 +40,97 @@ impl MetadataLoader for CraneliftMetadataLoader {
 
     fn get_dylib_metadata(
         &self,
-        _target: &crate::rustc_target::spec::Target,
-        _path: &Path,
+        target: &crate::rustc_target::spec::Target,
+        path: &Path,
     ) -> Result<owning_ref::ErasedBoxRef<[u8]>, String> {
-        Err("dylib metadata loading is not yet supported".to_string())
+        let buf = std::fs::read(path).map_err(|e| format!("{:?}", e))?;
+        let (start, end) = find_metadata_section(target, &buf)?;
+        let buf: OwningRef<Vec<u8>, [u8]> = OwningRef::new(buf).map(|buf| &buf[start..end]);
+        Ok(rustc_erase_owner!(buf.map_owner_box()))
+    }
+}
+
+fn metadata_section_name(
+    target: &crate::rustc_target::spec::Target,
+) -> (&'static str, &'static str) {
+    if target.options.is_like_osx {
+        ("__DATA", ".rustc")
+    } else {
+        ("", ".rustc")
     }
 }
+
+/// Returns the byte range of the metadata section in the given dylib.
+fn find_metadata_section(
+    target: &crate::rustc_target::spec::Target,
+    buf: &[u8],
+) -> Result<(usize, usize), String> {
+    let (_segment_name, section_name) = metadata_section_name(target);
+    match goblin::Object::parse(buf).map_err(|e| format!("{:?}", e))? {
+        goblin::Object::Elf(elf) => {
+            for section in &elf.section_headers {
+                let name = elf.shdr_strtab.get(section.sh_name);
+                if let Some(Ok(name)) = name {
+                    if name == section_name {
+                        let start = section.sh_offset as usize;
+                        return Ok((start, start + section.sh_size as usize));
+                    }
+                }
+            }
+        }
+        goblin::Object::Mach(goblin::mach::Mach::Binary(macho)) => {
+            for segment in macho.segments.iter() {
+                for section in segment.sections().map_err(|e| format!("{:?}", e))? {
+                    let (section, _data) = section;
+                    if section.name().ok() == Some(section_name) {
+                        let start = section.offset as usize;
+                        return Ok((start, start + section.size as usize));
+                    }
+                }
+            }
+        }
+        _ => return Err("unsupported dylib format".to_string()),
+    }
+    Err("couldn't find metadata section".to_string())
+}
+
+/// Embed the crate metadata in a section of the object file, so it can be read back by
+/// `get_dylib_metadata` once the object file has been linked into a dylib or proc macro.
+pub fn write_metadata<'a, 'tcx: 'a>(
+    tcx: TyCtxt<'a, 'tcx, 'tcx>,
+    module: &mut Module<impl Backend>,
+    object_patches: &mut ObjectPatches,
+    metadata: &EncodedMetadata,
+) {
+    let need_metadata_section = tcx
+        .sess
+        .crate_types
+        .borrow()
+        .iter()
+        .any(|crate_type| match *crate_type {
+            CrateType::Dylib | CrateType::ProcMacro => true,
+            _ => false,
+        });
+    if !need_metadata_section {
+        return;
+    }
+
+    // The metadata loader of rustc expects the compressed metadata to be prefixed with the
+    // uncompressed metadata header.
+    let mut compressed = tcx.metadata_encoding_version();
+    DeflateEncoder::new(&mut compressed, Compression::fast())
+        .write_all(&metadata.raw_data)
+        .unwrap();
+
+    let symbol_name = rustc::middle::exported_symbols::metadata_symbol_name(tcx);
+    let data_id = module
+        .declare_data(&symbol_name, Linkage::Export, false, None)
+        .unwrap();
+    let mut data_ctx = DataContext::new();
+    data_ctx.define(compressed.into_boxed_slice());
+    module.define_data(data_id, &data_ctx).unwrap();
+    let (_segment_name, section_name) = metadata_section_name(&tcx.sess.target.target);
+    object_patches
+        .link_sections
+        .insert(symbol_name, section_name.to_string());
+}
@@ -0,0 +1,199 @@
+//! Faerie and Cranelift can't express everything the object file of a crate needs, like TLS
+//! sections, `.bss` sections, custom sections, retained sections, hidden symbols, weak imports and
+//! big alignments. These parts are patched into the ELF object after faerie emitted it.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use goblin::elf::{section_header::*, sym::*, Elf};
+
+use crate::prelude::*;
+
+/// Size of an `Elf64_Ehdr`
+const EHDR_SIZE: usize = 64;
+/// Offset of `e_shoff` in an `Elf64_Ehdr`
+const E_SHOFF: usize = 40;
+/// Offset of `st_info` in an `Elf64_Sym`
+const ST_INFO: usize = 4;
+/// Offset of `st_other` in an `Elf64_Sym`
+const ST_OTHER: usize = 5;
+/// Offset of `EI_OSABI` in the `e_ident` of an `Elf64_Ehdr`
+const EI_OSABI: usize = 7;
+/// OS ABI of objects using GNU extensions
+const ELFOSABI_GNU: u8 = 3;
+/// Section flag telling the linker not to garbage collect the section. It is a GNU extension, so
+/// the linker only respects it in objects with the GNU OS ABI.
+const SHF_GNU_RETAIN: u64 = 0x200000;
+
+/// Changes to make to the symbols defined by the object file of a crate.
+#[derive(Default)]
+pub struct ObjectPatches {
+    /// Data symbols which are thread locals. They are moved into `.tdata` sections.
+    pub thread_locals: HashSet<String>,
+    /// Writable data symbols which are all zeros. They are moved into `.bss` or `.tbss` sections.
+    pub zero_initialized: HashSet<String>,
+    /// Symbols with a `#[link_section]` and the name of that section
+    pub link_sections: HashMap<String, String>,
+    /// Data symbols with `#[used]`. Their sections are kept by the linker, even when unreferenced.
+    pub used: HashSet<String>,
+    /// Symbols with hidden visibility
+    pub hidden: HashSet<String>,
+    /// Imported symbols which may be undefined at runtime
+    pub weak_imports: HashSet<String>,
+    /// Data symbols with an alignment bigger than Cranelift supports
+    pub alignments: HashMap<String, u64>,
+}
+
+impl ObjectPatches {
+    fn is_empty(&self) -> bool {
+        self.thread_locals.is_empty()
+            && self.zero_initialized.is_empty()
+            && self.link_sections.is_empty()
+            && self.used.is_empty()
+            && self.hidden.is_empty()
+            && self.weak_imports.is_empty()
+            && self.alignments.is_empty()
+    }
+}
+
+/// Runs on the workers of `crate::emit_pool`, so errors are returned instead of reported.
+pub fn patch_object(obj: Vec<u8>, patches: &ObjectPatches) -> Result<Vec<u8>, String> {
+    if patches.is_empty() {
+        return Ok(obj);
+    }
+
+    let elf = Elf::parse(&obj).map_err(|err| format!("Failed to parse object: {}", err))?;
+    if !elf.is_64 || !elf.little_endian {
+        return Err("Only 64bit little endian object files can be patched".to_string());
+    }
+
+    let mut section_headers = elf.section_headers.clone();
+    let shstrndx = elf.header.e_shstrndx as usize;
+    let mut shstrtab = section_data(&obj, &section_headers[shstrndx]).to_vec();
+    let (symtab_index, symtab_shdr) = section_headers
+        .iter()
+        .enumerate()
+        .find(|(_, shdr)| shdr.sh_type == SHT_SYMTAB)
+        .ok_or_else(|| "Object has no symbol table".to_string())?;
+    let mut symtab = section_data(&obj, symtab_shdr).to_vec();
+    let sym_size = symtab_shdr.sh_entsize as usize;
+
+    for (i, sym) in elf.syms.iter().enumerate() {
+        let name = match elf.strtab.get(sym.st_name) {
+            Some(Ok(name)) => name,
+            _ => return Err(format!("Symbol {} has an invalid name", i)),
+        };
+        let sym_entry = &mut symtab[i * sym_size..(i + 1) * sym_size];
+
+        if patches.hidden.contains(name) && sym.st_shndx != SHN_UNDEF as usize {
+            sym_entry[ST_OTHER] = (sym.st_other & !0x3) | STV_HIDDEN;
+        }
+
+        if patches.weak_imports.contains(name) && sym.st_shndx == SHN_UNDEF as usize {
+            sym_entry[ST_INFO] = (STB_WEAK << 4) | sym.st_type();
+        }
+
+        if sym.st_shndx == SHN_UNDEF as usize || sym.st_shndx >= section_headers.len() {
+            continue;
+        }
+        let shdr = &mut section_headers[sym.st_shndx];
+
+        let thread_local = patches.thread_locals.contains(name);
+        let zero_initialized = patches.zero_initialized.contains(name);
+        if thread_local {
+            shdr.sh_flags |= (SHF_WRITE | SHF_TLS) as u64;
+            sym_entry[ST_INFO] = (sym.st_bind() << 4) | STT_TLS;
+        }
+        if zero_initialized {
+            // Only writable data is zero initialized, so the section already has `SHF_WRITE`.
+            shdr.sh_type = SHT_NOBITS;
+        }
+        let section_name = if let Some(section_name) = patches.link_sections.get(name) {
+            Some(section_name.clone())
+        } else if thread_local || zero_initialized {
+            let prefix = match (thread_local, zero_initialized) {
+                (true, true) => ".tbss",
+                (true, false) => ".tdata",
+                (false, _) => ".bss",
+            };
+            Some(format!("{}.{}", prefix, name))
+        } else {
+            None
+        };
+        if let Some(section_name) = section_name {
+            // The new section names don't fit in the old section name table, so they are appended
+            // to it.
+            shdr.sh_name = shstrtab.len();
+            shstrtab.extend_from_slice(section_name.as_bytes());
+            shstrtab.push(0);
+        }
+        if patches.used.contains(name) {
+            shdr.sh_flags |= SHF_GNU_RETAIN;
+        }
+        if let Some(&align) = patches.alignments.get(name) {
+            shdr.sh_addralign = align;
+        }
+    }
+
+    // Lay out the object again, as section contents may have changed size or disappeared.
+    let mut patched = obj[..EHDR_SIZE].to_vec();
+    if !patches.used.is_empty() {
+        patched[EI_OSABI] = ELFOSABI_GNU;
+    }
+    for (i, shdr) in section_headers.iter_mut().enumerate() {
+        if shdr.sh_type == SHT_NULL {
+            continue;
+        }
+        if shdr.sh_type == SHT_NOBITS {
+            shdr.sh_offset = patched.len() as u64;
+            continue;
+        }
+        let data = if i == shstrndx {
+            &shstrtab[..]
+        } else if i == symtab_index {
+            &symtab[..]
+        } else {
+            section_data(&obj, shdr)
+        };
+        pad_to_align(&mut patched, shdr.sh_addralign);
+        shdr.sh_offset = patched.len() as u64;
+        shdr.sh_size = data.len() as u64;
+        patched.extend_from_slice(data);
+    }
+
+    pad_to_align(&mut patched, 8);
+    let shoff = patched.len() as u64;
+    (&mut patched[E_SHOFF..E_SHOFF + 8])
+        .write_u64::<LittleEndian>(shoff)
+        .unwrap();
+    for shdr in &section_headers {
+        write_section_header(&mut patched, shdr);
+    }
+
+    Ok(patched)
+}
+
+fn section_data<'a>(obj: &'a [u8], shdr: &SectionHeader) -> &'a [u8] {
+    if shdr.sh_type == SHT_NOBITS {
+        return &[];
+    }
+    &obj[shdr.sh_offset as usize..][..shdr.sh_size as usize]
+}
+
+fn pad_to_align(obj: &mut Vec<u8>, align: u64) {
+    let align = align.max(1) as usize;
+    let padded_len = (obj.len() + align - 1) / align * align;
+    obj.resize(padded_len, 0);
+}
+
+/// Writes an `Elf64_Shdr`
+fn write_section_header(obj: &mut Vec<u8>, shdr: &SectionHeader) {
+    obj.write_u32::<LittleEndian>(shdr.sh_name as u32).unwrap();
+    obj.write_u32::<LittleEndian>(shdr.sh_type).unwrap();
+    obj.write_u64::<LittleEndian>(shdr.sh_flags).unwrap();
+    obj.write_u64::<LittleEndian>(shdr.sh_addr).unwrap();
+    obj.write_u64::<LittleEndian>(shdr.sh_offset).unwrap();
+    obj.write_u64::<LittleEndian>(shdr.sh_size).unwrap();
+    obj.write_u32::<LittleEndian>(shdr.sh_link).unwrap();
+    obj.write_u32::<LittleEndian>(shdr.sh_info).unwrap();
+    obj.write_u64::<LittleEndian>(shdr.sh_addralign).unwrap();
+    obj.write_u64::<LittleEndian>(shdr.sh_entsize).unwrap();
+}
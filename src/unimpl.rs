@@ -8,6 +8,7 @@ use std::io::Write;
 use syntax::source_map::Span;
 
 use rustc::ty::TyCtxt;
+use rustc_data_structures::sync::Lock;
 
 thread_local! {
     static SPAN_STACK: RefCell<Vec<Span>> = RefCell::new(vec![]);
@@ -20,7 +21,7 @@ pub macro unimpl($($tt:tt)*) {
     panic!(NonFatal(format!($($tt)*)));
 }
 
-pub fn try_unimpl(tcx: TyCtxt, log: &mut Option<File>, f: impl FnOnce()) {
+pub fn try_unimpl(tcx: TyCtxt, log: &Lock<Option<File>>, f: impl FnOnce()) {
     let res = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| f()));
 
     if let Err(err) = res {
@@ -29,7 +30,7 @@ pub fn try_unimpl(tcx: TyCtxt, log: &mut Option<File>, f: impl FnOnce()) {
                 Ok(non_fatal) => {
                     if cfg!(debug_assertions) {
                         writeln!(
-                            log.as_mut().unwrap(),
+                            log.lock().as_mut().unwrap(),
                             "{} at {:?}",
                             &non_fatal.0,
                             span_stack.borrow()
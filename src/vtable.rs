@@ -6,6 +6,19 @@ const DROP_FN_INDEX: usize = 0;
 const SIZE_INDEX: usize = 1;
 const ALIGN_INDEX: usize = 2;
 
+pub fn drop_fn_of_obj<'a, 'tcx: 'a>(
+    fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
+    vtable: Value,
+) -> Value {
+    let usize_size = fx.layout_of(fx.tcx.types.usize).size.bytes() as usize;
+    fx.bcx.ins().load(
+        pointer_ty(fx.tcx),
+        MemFlags::new(),
+        vtable,
+        (DROP_FN_INDEX * usize_size) as i32,
+    )
+}
+
 pub fn size_of_obj<'a, 'tcx: 'a>(
     fx: &mut FunctionCx<'a, 'tcx, impl Backend>,
     val: CValue<'tcx>,
@@ -75,18 +88,23 @@ fn build_vtable<'a, 'tcx: 'a>(
     let tcx = fx.tcx;
     let usize_size = fx.layout_of(fx.tcx.types.usize).size.bytes() as usize;
 
-    let drop_in_place_fn = fx.get_function_id(
-        crate::rustc_mir::monomorphize::resolve_drop_in_place(tcx, ty),
-    );
+    let drop_in_place_fn = match crate::rustc_mir::monomorphize::resolve_drop_in_place(tcx, ty) {
+        // A null drop slot means there is nothing to drop
+        Instance {
+            def: InstanceDef::DropGlue(_, None),
+            ..
+        } => None,
+        drop_fn => Some(fx.get_function_id(drop_fn)),
+    };
 
-    let mut components: Vec<_> = vec![Some(drop_in_place_fn), None, None];
+    let mut components: Vec<_> = vec![drop_in_place_fn, None, None];
 
     let trait_ref = trait_ref.with_self_ty(tcx, ty);
     let methods = tcx.vtable_methods(trait_ref);
     let methods = methods.iter().cloned().map(|opt_mth| {
         opt_mth.map_or(None, |(def_id, substs)| {
             Some(fx.get_function_id(
-                Instance::resolve(tcx, ParamEnv::reveal_all(), def_id, substs).unwrap(),
+                Instance::resolve_for_vtable(tcx, ParamEnv::reveal_all(), def_id, substs).unwrap(),
             ))
         })
     });
@@ -116,6 +134,7 @@ fn build_vtable<'a, 'tcx: 'a>(
             &format!("vtable.{:?}.for.{:?}", trait_ref, ty),
             Linkage::Local,
             false,
+            crate::constant::clif_data_align(fx.tcx.data_layout.pointer_align.abi.bytes()),
         )
         .unwrap();
     fx.module.define_data(data_id, &data_ctx).unwrap();
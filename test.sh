@@ -20,6 +20,95 @@ SHOULD_RUN=1 $RUSTC --crate-type bin example/mini_core_hello_world.rs --cfg jit
 
 echo "[AOT] mini_core_hello_world"
 build_example_bin mini_core_hello_world example/mini_core_hello_world.rs
+# The section of the page aligned static must be page aligned too
+readelf -S -W target/out/mini_core_hello_world.*.rcgu.o \
+    | awk '/PAGE_ALIGNED/ { found = 1; if ($NF < 4096) bad = 1 } END { exit !found || bad }'
+nm target/out/mini_core_hello_world | awk '/PAGE_ALIGNED/ { found = 1; if ($1 !~ /000$/) bad = 1 } END { exit !found || bad }'
+# The unreferenced #[used] static must survive --gc-sections in its #[link_section]
+objdump -h target/out/mini_core_hello_world | grep -q ' \.custom_section '
+objdump -t target/out/mini_core_hello_world | grep IN_CUSTOM_SECTION | grep -q '\.custom_section'
+objdump -t target/out/mini_core_hello_world | grep in_custom_text_section | grep -q '\.custom_text'
+# extern_weak statics refer to weak undefined symbols
+objdump -t target/out/mini_core_hello_world.*.rcgu.o | grep -qE ' w +\*UND\*.* __cg_clif_missing_weak_symbol$'
+
+echo "[AOT] shl_overflow"
+# The overflowing shift aborts through the panic lang item of mini_core
+$RUSTC example/shl_overflow.rs --crate-type bin
+exit_code=0
+./target/out/shl_overflow || exit_code=$?
+[ $exit_code -gt 128 ]
+
+echo "[AOT] mini_core_hello_world (dylib mini_core)"
+mkdir -p target/out/dylib
+# mini_core has no compiler-builtins, so the i128 helpers come from libgcc
+rustc $RUSTFLAGS example/mini_core.rs --crate-name mini_core --crate-type dylib --out-dir target/out/dylib \
+    -Clink-arg=-lgcc
+rustc $RUSTFLAGS example/mini_core_hello_world.rs --crate-type bin -Cprefer-dynamic \
+    --crate-name mini_core_hello_world_dylib -L crate=target/out/dylib --out-dir target/out/dylib
+# The example exits with 44, see the `Termination` impl in it
+exit_code=0
+LD_LIBRARY_PATH=target/out/dylib ./target/out/dylib/mini_core_hello_world_dylib || exit_code=$?
+[ $exit_code -eq 44 ]
+
+echo "[AOT] mini_core_hello_world (debuginfo)"
+$RUSTC example/mini_core_hello_world.rs --crate-type bin --crate-name mini_core_hello_world_dbg -g
+./target/out/mini_core_hello_world_dbg || true
+objdump --dwarf=decodedline target/out/mini_core_hello_world_dbg | grep -q mini_core_hello_world.rs
+objdump --dwarf=info target/out/mini_core_hello_world_dbg \
+    | awk '/Abbrev Number/ { var = /DW_TAG_variable/; name = 0 } var && /DW_AT_name/ { name = 1 } var && name && /DW_AT_location/ { found = 1 } END { exit !found }'
+objdump --dwarf=info target/out/mini_core_hello_world_dbg \
+    | awk '/Abbrev Number/ { var = /DW_TAG_variable/ } var && /DW_AT_name.*: niche_filled_fn_ref$/ { found = 1 } END { exit !found }'
+objdump --dwarf=info target/out/mini_core_hello_world_dbg | grep -q DW_AT_discr_list
+
+echo "[AOT] mini_core_hello_world (incremental)"
+# The second build should reuse the objects of all codegen units from the first one
+$RUSTC example/mini_core_hello_world.rs --crate-type bin --crate-name mini_core_hello_world_incr -Cincremental=target/out/incremental
+$RUSTC example/mini_core_hello_world.rs --crate-type bin --crate-name mini_core_hello_world_incr -Cincremental=target/out/incremental \
+    -Zincremental-info > target/out/incremental_info.txt
+grep -qE 'Re-using ([1-9][0-9]*) out of \1 codegen units' target/out/incremental_info.txt
+exit_code=0
+./target/out/mini_core_hello_world_incr || exit_code=$?
+[ $exit_code -eq 44 ]
+
+# After changing one function only the codegen unit containing it should be recompiled
+cp example/incremental.rs target/out/incremental_change.rs
+$RUSTC target/out/incremental_change.rs --crate-type bin -Cincremental=target/out/incremental_change_cache
+sed -i 's/^        2$/        3/' target/out/incremental_change.rs
+$RUSTC target/out/incremental_change.rs --crate-type bin -Cincremental=target/out/incremental_change_cache \
+    -Zincremental-info > target/out/incremental_info.txt
+awk '/Re-using/ { found = 1; if ($3 == 0 || $3 >= $6) bad = 1 } END { exit !found || bad }' target/out/incremental_info.txt
+exit_code=0
+./target/out/incremental_change || exit_code=$?
+[ $exit_code -eq 4 ]
+
+echo "[AOT] c_abi"
+cc -c example/c_abi.c -o target/out/c_abi.o
+ar rcs target/out/libc_abi.a target/out/c_abi.o
+# A failing assert aborts, so the exit code of the example has to be checked
+$RUSTC example/c_abi.rs -L target/out --crate-name c_abi --crate-type bin
+./target/out/c_abi
+
+echo "[AOT] rust_lib (staticlib and cdylib)"
+$RUSTC example/rust_lib.rs --crate-type staticlib,cdylib
+nm -D --defined-only target/out/librust_lib.so | grep -q ' rust_add$'
+[ -z "$(nm -D --defined-only target/out/librust_lib.so | grep _ZN)" ]
+cc example/use_rust_lib.c target/out/librust_lib.a -o target/out/use_rust_lib_static
+./target/out/use_rust_lib_static
+cc example/use_rust_lib.c -Ltarget/out -lrust_lib -o target/out/use_rust_lib_dynamic
+LD_LIBRARY_PATH=target/out ./target/out/use_rust_lib_dynamic
+
+echo "[BUILD] proc_macro_example"
+# Proc macros are linked against the libstd of the host, which doesn't use panic=abort
+rustc -Zcodegen-backend=$(pwd)/target/$channel/librustc_codegen_cranelift.$dylib_ext \
+    example/proc_macro_example.rs --crate-type proc-macro --out-dir target/out
+rustc example/use_proc_macro.rs -L crate=target/out -o target/out/use_proc_macro
+./target/out/use_proc_macro
+
+echo "[AOT] async_example"
+# Like proc macros async fn need libstd, so the libstd of the host is used
+rustc -Zcodegen-backend=$(pwd)/target/$channel/librustc_codegen_cranelift.$dylib_ext \
+    --edition 2018 example/async_example.rs --crate-type bin --out-dir target/out
+./target/out/async_example
 
 pushd xargo
 rm -r ~/.xargo/HOST || true
@@ -27,9 +116,9 @@ rm -r target || true
 time xargo build
 popd
 
-# TODO linux linker doesn't accept duplicate definitions
-#$RUSTC --sysroot ~/.xargo/HOST example/alloc_example.rs --crate-type bin
-#./target/out/alloc_example
+echo "[AOT] alloc_example"
+$RUSTC --sysroot ~/.xargo/HOST example/alloc_example.rs --crate-type bin
+./target/out/alloc_example
 
 $RUSTC --sysroot ~/.xargo/HOST example/mod_bench.rs --crate-type bin
 